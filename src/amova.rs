@@ -0,0 +1,486 @@
+use crate::distance::Dissimilarity;
+use crate::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// A level of a hierarchical sampling design
+pub enum Stratum {
+    /// A named level whose units are the listed `Group`s
+    ///
+    /// Individuals belong to the first listed group they are a member of.
+    Groups(String, Vec<String>),
+    /// A level whose units are the values of a `Meta` field
+    Meta(String),
+}
+
+impl Stratum {
+    /// The name of this level
+    pub fn name(&self) -> &str {
+        match self {
+            Stratum::Groups(name, _) => name,
+            Stratum::Meta(key) => key,
+        }
+    }
+
+    /// The unit of this level an individual belongs to
+    fn unit(&self, individual: &Individual) -> Option<String> {
+        match self {
+            Stratum::Groups(_, groups) => groups.iter().find(|g| individual.in_group(g)).cloned(),
+            Stratum::Meta(key) => individual.meta.get(key).cloned(),
+        }
+    }
+}
+
+/// Analysis of molecular variance (Excoffier et al. 1992)
+///
+/// Strata are nested from the outermost, such as region, to the
+/// innermost, such as site, and individuals are the units within the
+/// innermost stratum. Individuals without a unit at every stratum are
+/// left out. Sums of squares are computed from squared dissimilarities,
+/// so Euclidean-like distances suit best. Variance components of
+/// unbalanced designs follow the expected mean squares of nested random
+/// effects.
+pub struct Amova {
+    strata: Vec<Stratum>,
+    permutations: usize,
+    seed: Option<u64>,
+}
+
+impl Default for Amova {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Amova {
+    /// Construct a new analysis without strata and with 999 permutations
+    pub fn new() -> Self {
+        Self {
+            strata: vec![],
+            permutations: 999,
+            seed: None,
+        }
+    }
+
+    /// Adds a stratum nested within the strata added before it
+    pub fn stratum(&mut self, stratum: Stratum) -> &mut Self {
+        self.strata.push(stratum);
+        self
+    }
+
+    /// The number of permutations of each test, or 0 to skip the tests
+    pub fn permutations(&mut self, permutations: usize) -> &mut Self {
+        self.permutations = permutations;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// One source of variation of an `AmovaSummary`
+pub struct AmovaLevel {
+    stratum: Option<String>,
+    degrees_of_freedom: usize,
+    sum_of_squares: f32,
+    variance_component: f32,
+    phi: Option<f32>,
+    p_value: Option<f32>,
+}
+
+impl AmovaLevel {
+    /// The stratum whose units vary within the enclosing stratum, or
+    /// `None` for individuals within the innermost stratum
+    pub fn stratum(&self) -> Option<&str> {
+        self.stratum.as_deref()
+    }
+
+    pub fn degrees_of_freedom(&self) -> usize {
+        self.degrees_of_freedom
+    }
+
+    pub fn sum_of_squares(&self) -> f32 {
+        self.sum_of_squares
+    }
+
+    pub fn mean_square(&self) -> f32 {
+        self.sum_of_squares / self.degrees_of_freedom as f32
+    }
+
+    pub fn variance_component(&self) -> f32 {
+        self.variance_component
+    }
+
+    /// The proportion of the variance within the enclosing stratum that
+    /// lies between units of this stratum, such as `Phi_CT` and `Phi_SC`
+    pub fn phi(&self) -> Option<f32> {
+        self.phi
+    }
+
+    /// The proportion of permutations with a variance component at
+    /// least as large, if permutations were run
+    ///
+    /// Units of the stratum below are permuted among the units of this
+    /// stratum, within the units of the enclosing stratum.
+    pub fn p_value(&self) -> Option<f32> {
+        self.p_value
+    }
+}
+
+/// The partition of molecular variance among the strata of a design
+pub struct AmovaSummary {
+    levels: Vec<AmovaLevel>,
+    within: AmovaLevel,
+}
+
+impl AmovaSummary {
+    /// The variation between the units of every stratum, outermost first
+    pub fn levels(&self) -> &[AmovaLevel] {
+        &self.levels
+    }
+
+    /// The variation between individuals within the innermost stratum
+    pub fn within(&self) -> &AmovaLevel {
+        &self.within
+    }
+
+    pub fn total_sum_of_squares(&self) -> f32 {
+        self.levels.iter().map(|l| l.sum_of_squares).sum::<f32>() + self.within.sum_of_squares
+    }
+
+    pub fn total_variance(&self) -> f32 {
+        self.levels
+            .iter()
+            .map(|l| l.variance_component)
+            .sum::<f32>()
+            + self.within.variance_component
+    }
+
+    /// The proportion of the total variance between units of the
+    /// innermost stratum, `Phi_ST`
+    pub fn phi_st(&self) -> f32 {
+        1.0 - self.within.variance_component / self.total_variance()
+    }
+}
+
+pub trait MolecularVariance {
+    /// Partitions the variance of `measure` among the strata of `amova`
+    fn amova<D: Dissimilarity>(
+        &mut self,
+        amova: &Amova,
+        measure: &D,
+    ) -> Result<AmovaSummary, Box<dyn Error>>;
+}
+
+/// Squared distances and the unit of every individual at every stratum
+struct Design {
+    squared: ndarray::Array2<f64>,
+    units: Vec<Vec<usize>>,
+    counts: Vec<usize>,
+}
+
+/// The sums of squares, degrees of freedom and variance components of
+/// every stratum followed by the within level
+struct Decomposition {
+    sums_of_squares: Vec<f64>,
+    degrees_of_freedom: Vec<usize>,
+    variances: Vec<f64>,
+}
+
+impl Design {
+    /// The sum of squares within the units of a partition
+    fn within(&self, units: &[usize], count: usize) -> f64 {
+        let mut sums = vec![0.0; count];
+        let mut sizes = vec![0usize; count];
+        for i in 0..units.len() {
+            sizes[units[i]] += 1;
+            for j in (i + 1)..units.len() {
+                if units[i] == units[j] {
+                    sums[units[i]] += self.squared[[i, j]];
+                }
+            }
+        }
+        sums.iter()
+            .zip(sizes.iter())
+            .map(|(sum, size)| sum / *size as f64)
+            .sum()
+    }
+
+    fn decompose(&self, units: &[Vec<usize>]) -> Decomposition {
+        let n = self.squared.nrows();
+        let levels = units.len();
+        let total = vec![0; n];
+        let partition = |h: usize| -> (&[usize], usize) {
+            match h {
+                0 => (&total, 1),
+                h => (&units[h - 1], self.counts[h - 1]),
+            }
+        };
+
+        let within: Vec<f64> = (0..=levels)
+            .map(|h| {
+                let (units, count) = partition(h);
+                self.within(units, count)
+            })
+            .collect();
+        let mut sums_of_squares: Vec<f64> =
+            (1..=levels).map(|h| within[h - 1] - within[h]).collect();
+        sums_of_squares.push(within[levels]);
+        let mut degrees_of_freedom: Vec<usize> = (1..=levels)
+            .map(|h| partition(h).1 - partition(h - 1).1)
+            .collect();
+        degrees_of_freedom.push(n - partition(levels).1);
+
+        // sizes[h][u] is the number of individuals in unit u of partition h
+        let sizes: Vec<Vec<f64>> = (0..=levels)
+            .map(|h| {
+                let (units, count) = partition(h);
+                let mut sizes = vec![0.0; count];
+                for unit in units {
+                    sizes[*unit] += 1.0;
+                }
+                sizes
+            })
+            .collect();
+        // The sum over the units of stratum k of n_unit^2 / n_ancestor at stratum m
+        let ratio = |k: usize, m: usize| -> f64 {
+            let mut seen = vec![false; partition(k).1];
+            let mut sum = 0.0;
+            for i in 0..n {
+                let unit = partition(k).0[i];
+                if !seen[unit] {
+                    seen[unit] = true;
+                    sum += sizes[k][unit].powi(2) / sizes[m][partition(m).0[i]];
+                }
+            }
+            sum
+        };
+
+        let mean_square = |h: usize| sums_of_squares[h] / degrees_of_freedom[h] as f64;
+        let mut variances = vec![0.0; levels + 1];
+        variances[levels] = mean_square(levels);
+        for h in (1..=levels).rev() {
+            let coefficient =
+                |k: usize| (ratio(k, h) - ratio(k, h - 1)) / degrees_of_freedom[h - 1] as f64;
+            let nested: f64 = ((h + 1)..=levels)
+                .map(|k| coefficient(k) * variances[k - 1])
+                .sum();
+            variances[h - 1] = (mean_square(h - 1) - variances[levels] - nested) / coefficient(h);
+        }
+
+        Decomposition {
+            sums_of_squares,
+            degrees_of_freedom,
+            variances,
+        }
+    }
+
+    /// Permutes the units below stratum `h` among its units, within the
+    /// units of the enclosing stratum
+    fn permuted<R: Rng>(&self, h: usize, rng: &mut R) -> Vec<Vec<usize>> {
+        let n = self.squared.nrows();
+        let below = |i: usize| {
+            if h + 1 < self.units.len() {
+                self.units[h + 1][i]
+            } else {
+                i
+            }
+        };
+        let above = |i: usize| if h > 0 { self.units[h - 1][i] } else { 0 };
+
+        let mut members: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+        let mut seen = std::collections::HashSet::new();
+        for i in 0..n {
+            if seen.insert(below(i)) {
+                members
+                    .entry(above(i))
+                    .or_default()
+                    .push((below(i), self.units[h][i]));
+            }
+        }
+        let mut assignment = HashMap::new();
+        let mut parents: Vec<_> = members.into_iter().collect();
+        parents.sort();
+        for (_, children) in parents {
+            let mut labels: Vec<usize> = children.iter().map(|(_, label)| *label).collect();
+            labels.shuffle(rng);
+            for ((child, _), label) in children.iter().zip(labels) {
+                assignment.insert(*child, label);
+            }
+        }
+
+        let mut units = self.units.clone();
+        for i in 0..n {
+            units[h][i] = assignment[&below(i)];
+        }
+        units
+    }
+}
+
+impl MolecularVariance for Sample {
+    fn amova<D: Dissimilarity>(
+        &mut self,
+        amova: &Amova,
+        measure: &D,
+    ) -> Result<AmovaSummary, Box<dyn Error>> {
+        if amova.strata.is_empty() {
+            return Err("AMOVA needs at least one stratum".into());
+        }
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+
+        let mut rows = vec![];
+        let mut keys: Vec<Vec<Vec<String>>> = vec![];
+        for (row, individual) in self.individuals.values().enumerate() {
+            let values: Option<Vec<String>> =
+                amova.strata.iter().map(|s| s.unit(individual)).collect();
+            if let Some(values) = values {
+                rows.push(row);
+                keys.push((1..=values.len()).map(|h| values[..h].to_vec()).collect());
+            }
+        }
+        if rows.len() < 2 {
+            return Err("AMOVA needs at least two individuals with every stratum".into());
+        }
+
+        let mut units = vec![vec![0; rows.len()]; amova.strata.len()];
+        let mut counts = vec![];
+        for (h, level) in units.iter_mut().enumerate() {
+            let mut ids: HashMap<&Vec<String>, usize> = HashMap::new();
+            for (i, key) in keys.iter().enumerate() {
+                let next = ids.len();
+                level[i] = *ids.entry(&key[h]).or_insert(next);
+            }
+            counts.push(ids.len());
+        }
+
+        let mut squared = ndarray::Array2::<f64>::zeros((rows.len(), rows.len()));
+        for i in 0..rows.len() {
+            for j in (i + 1)..rows.len() {
+                let d = measure.dissimilarity(&self.matrix, rows[i], rows[j]) as f64;
+                if d.is_nan() {
+                    return Err(
+                        "AMOVA needs every pair of individuals to share a typed locus".into(),
+                    );
+                }
+                squared[[i, j]] = d * d;
+                squared[[j, i]] = d * d;
+            }
+        }
+
+        let design = Design {
+            squared,
+            units,
+            counts,
+        };
+        let observed = design.decompose(&design.units);
+        let levels = amova.strata.len();
+        let mut rng = self.random.or_seed(amova.seed).rng();
+        let p_values: Vec<Option<f32>> = (0..levels)
+            .map(|h| {
+                if amova.permutations == 0 {
+                    return None;
+                }
+                let extreme = (0..amova.permutations)
+                    .filter(|_| {
+                        design.decompose(&design.permuted(h, &mut rng)).variances[h]
+                            >= observed.variances[h] - 1e-12
+                    })
+                    .count();
+                Some((extreme + 1) as f32 / (amova.permutations + 1) as f32)
+            })
+            .collect();
+
+        let level = |h: usize, stratum: Option<String>, phi: Option<f64>, p_value: Option<f32>| {
+            AmovaLevel {
+                stratum,
+                degrees_of_freedom: observed.degrees_of_freedom[h],
+                sum_of_squares: observed.sums_of_squares[h] as f32,
+                variance_component: observed.variances[h] as f32,
+                phi: phi.map(|phi| phi as f32),
+                p_value,
+            }
+        };
+        Ok(AmovaSummary {
+            levels: (0..levels)
+                .map(|h| {
+                    let enclosed: f64 = observed.variances[h..].iter().sum();
+                    let phi = observed.variances[h] / enclosed;
+                    level(
+                        h,
+                        Some(amova.strata[h].name().to_string()),
+                        Some(phi),
+                        p_values[h],
+                    )
+                })
+                .collect(),
+            within: level(levels, None, None, None),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::Prevosti;
+    use crate::observable::CsvBuilder;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_amova_partitions_variance() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        let meta: HashSet<String> = ["region", "site"].iter().map(|s| s.to_string()).collect();
+        sample.observe(
+            CsvBuilder::new().meta_fields(meta).from_reader(Box::new(
+                "region,site,a,b\n\
+             n,n1,1/1,1/1\nn,n1,1/1,1/2\nn,n1,1/2,1/1\nn,n2,1/1,2/2\nn,n2,1/2,2/2\n\
+             s,s1,2/2,1/1\ns,s1,2/3,1/2\ns,s2,2/2,2/2\ns,s2,3/3,2/2\ns,s2,2/3,2/2"
+                    .as_bytes(),
+            ))?,
+        )?;
+
+        let mut amova = Amova::new();
+        amova
+            .stratum(Stratum::Meta("region".into()))
+            .permutations(99)
+            .seed(1);
+        let one = sample.amova(&amova, &Prevosti)?;
+        assert_eq!(one.levels()[0].degrees_of_freedom(), 1);
+        assert_eq!(one.within().degrees_of_freedom(), 8);
+        assert!(one.levels()[0].phi().unwrap() > 0.3);
+        assert!(one.levels()[0].p_value().unwrap() < 0.05);
+
+        amova.stratum(Stratum::Meta("site".into()));
+        let two = sample.amova(&amova, &Prevosti)?;
+        assert!((two.total_sum_of_squares() - one.total_sum_of_squares()).abs() < 1e-5);
+        assert_eq!(two.levels()[1].stratum(), Some("site"));
+        assert_eq!(two.levels()[1].degrees_of_freedom(), 2);
+        assert_eq!(two.within().degrees_of_freedom(), 6);
+        assert!(two.levels().iter().all(|l| l.p_value().is_some()));
+        assert!(two.phi_st() > two.levels()[1].phi().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_amova_of_fixed_differences() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .group_field("pop")
+                .from_reader(Box::new("pop,a\nx,1/1\nx,1/1\ny,2/2\ny,2/2".as_bytes()))?,
+        )?;
+        let pops = Stratum::Groups("pop".into(), vec!["x".into(), "y".into()]);
+        let summary = sample.amova(Amova::new().stratum(pops).permutations(0), &Prevosti)?;
+        let among = &summary.levels()[0];
+        assert!((among.sum_of_squares() - 1.0).abs() < 1e-6);
+        assert!((among.variance_component() - 0.5).abs() < 1e-6);
+        assert_eq!(among.phi(), Some(1.0));
+        assert_eq!(among.p_value(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,600 @@
+use crate::linalg::{backward_substitute, cholesky, forward_substitute, symmetric_eigen};
+use crate::pca::principal_axes;
+use crate::prelude::*;
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+use rand::seq::SliceRandom;
+use std::error::Error;
+
+/// Discriminant analysis of principal components (Jombart et al. 2010)
+///
+/// The individuals' allele frequencies are centred and reduced to their
+/// first principal components, on which a linear discriminant analysis
+/// separates the `Group`s. The prior group of an individual is the first
+/// of its groups by name. Individuals without a group are left out of
+/// the fit but are still assigned. Prior probabilities of membership are
+/// proportional to group sizes.
+pub struct Dapc {
+    components: Option<usize>,
+}
+
+impl Default for Dapc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dapc {
+    /// Construct a new analysis retaining a third as many principal
+    /// components as there are grouped individuals
+    pub fn new() -> Self {
+        Self { components: None }
+    }
+
+    /// The number of principal components to retain
+    pub fn components(&mut self, components: usize) -> &mut Self {
+        self.components = Some(components);
+        self
+    }
+}
+
+/// Cross-validation of the number of principal components retained by `Dapc`
+///
+/// Every replicate fits the analysis to a random `training` proportion of
+/// each group and assigns the remaining individuals. The same replicates
+/// are used for every number of components.
+pub struct DapcCrossValidation {
+    components: Option<Vec<usize>>,
+    training: f32,
+    replicates: usize,
+    seed: Option<u64>,
+}
+
+impl Default for DapcCrossValidation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DapcCrossValidation {
+    /// Construct a new cross-validation of 30 replicates training on 90%
+    /// of each group, trying every number of components the data allows
+    pub fn new() -> Self {
+        Self {
+            components: None,
+            training: 0.9,
+            replicates: 30,
+            seed: None,
+        }
+    }
+
+    /// The numbers of principal components to try
+    pub fn components(&mut self, components: Vec<usize>) -> &mut Self {
+        self.components = Some(components);
+        self
+    }
+
+    /// The proportion of each group fitted in every replicate
+    ///
+    /// At least one individual of each group is fitted and, when the group
+    /// has two or more, at least one is held out.
+    pub fn training(&mut self, training: f32) -> &mut Self {
+        self.training = training;
+        self
+    }
+
+    /// The number of random training and held out splits
+    pub fn replicates(&mut self, replicates: usize) -> &mut Self {
+        self.replicates = replicates;
+        self
+    }
+
+    /// Seeds the random splits, making the cross-validation reproducible
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// The result of a discriminant analysis of principal components
+pub struct DapcSummary {
+    groups: Vec<String>,
+    individual_names: Vec<String>,
+    prior_groups: Vec<Option<usize>>,
+    eigenvalues: Vec<f32>,
+    coefficients: Array2<f32>,
+    allele_coefficients: Array2<f32>,
+    alleles: Vec<(String, String)>,
+    scores: Array2<f32>,
+    posterior: Array2<f32>,
+}
+
+impl DapcSummary {
+    /// The names of the prior groups, in the order of `posterior()`'s columns
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    /// The name of the `Individual` in each row of `scores()` and `posterior()`
+    pub fn individual_names(&self) -> &[String] {
+        &self.individual_names
+    }
+
+    /// The prior group of every individual
+    pub fn prior_groups(&self) -> Vec<Option<&str>> {
+        self.prior_groups
+            .iter()
+            .map(|group| group.map(|g| self.groups[g].as_str()))
+            .collect()
+    }
+
+    /// The ratio of between to within group variance along every
+    /// discriminant function
+    pub fn eigenvalues(&self) -> &[f32] {
+        &self.eigenvalues
+    }
+
+    /// The discriminant functions, one row per retained principal
+    /// component and one column per function
+    pub fn coefficients(&self) -> &Array2<f32> {
+        &self.coefficients
+    }
+
+    /// The discriminant functions expressed on the allele frequencies,
+    /// one row per allele and one column per function
+    pub fn allele_coefficients(&self) -> &Array2<f32> {
+        &self.allele_coefficients
+    }
+
+    /// The `(locus, variation)` names of each row of `allele_coefficients()`
+    pub fn alleles(&self) -> &[(String, String)] {
+        &self.alleles
+    }
+
+    /// The coordinates of the individuals on the discriminant functions
+    pub fn scores(&self) -> &Array2<f32> {
+        &self.scores
+    }
+
+    /// The posterior probability of each individual belonging to each group
+    pub fn posterior(&self) -> &Array2<f32> {
+        &self.posterior
+    }
+
+    /// The group each individual most probably belongs to
+    pub fn assignments(&self) -> Vec<&str> {
+        self.posterior
+            .genrows()
+            .into_iter()
+            .map(|row| self.groups[most_probable(row)].as_str())
+            .collect()
+    }
+
+    /// The proportion of individuals with a prior group assigned to it
+    pub fn accuracy(&self) -> f32 {
+        let assigned: Vec<(usize, usize)> = self
+            .prior_groups
+            .iter()
+            .zip(self.posterior.genrows())
+            .filter_map(|(prior, row)| Some(((*prior)?, most_probable(row))))
+            .collect();
+        assigned
+            .iter()
+            .filter(|(prior, group)| prior == group)
+            .count() as f32
+            / assigned.len() as f32
+    }
+}
+
+/// The proportion of held out individuals assigned to their prior group
+/// for every number of principal components tried
+pub struct CrossValidationSummary {
+    components: Vec<usize>,
+    accuracy: Vec<f32>,
+}
+
+impl CrossValidationSummary {
+    pub fn components(&self) -> &[usize] {
+        &self.components
+    }
+
+    pub fn accuracy(&self) -> &[f32] {
+        &self.accuracy
+    }
+
+    /// The number of components with the highest accuracy, the fewest
+    /// on ties
+    pub fn best(&self) -> usize {
+        let mut best = 0;
+        for (i, accuracy) in self.accuracy.iter().enumerate() {
+            if *accuracy > self.accuracy[best] {
+                best = i;
+            }
+        }
+        self.components[best]
+    }
+}
+
+fn most_probable<S: ndarray::Data<Elem = f32>>(row: ndarray::ArrayBase<S, ndarray::Ix1>) -> usize {
+    let mut best = 0;
+    for (i, p) in row.iter().enumerate() {
+        if *p > row[best] {
+            best = i;
+        }
+    }
+    best
+}
+
+pub trait DiscriminantAnalysis {
+    /// Computes a discriminant analysis of principal components between `Group`s
+    fn dapc(&mut self, dapc: &Dapc) -> Result<DapcSummary, Box<dyn Error>>;
+
+    /// Cross-validates the number of principal components retained
+    fn dapc_cross_validation(
+        &mut self,
+        validation: &DapcCrossValidation,
+    ) -> Result<CrossValidationSummary, Box<dyn Error>>;
+}
+
+/// A discriminant analysis fitted to some rows of a frequency matrix
+struct Model {
+    /// The mean frequency of every column in the fitted rows
+    means: Array1<f64>,
+    /// The loadings of the retained principal components
+    loadings: Array2<f64>,
+    /// The Cholesky factor of the pooled within group covariance
+    within: Array2<f64>,
+    /// The mean principal scores of every group
+    centroids: Array2<f64>,
+    log_priors: Vec<f64>,
+    eigenvalues: Vec<f64>,
+    /// The discriminant functions on the principal components
+    axes: Array2<f64>,
+}
+
+impl Model {
+    fn fit(
+        x: &Array2<f64>,
+        rows: &[usize],
+        labels: &[usize],
+        groups: usize,
+        components: usize,
+    ) -> Result<Model, Box<dyn Error>> {
+        let training = x.select(Axis(0), rows);
+        let means: Array1<f64> = training
+            .gencolumns()
+            .into_iter()
+            .map(|column| {
+                let typed: Vec<f64> = column.iter().copied().filter(|f| !f.is_nan()).collect();
+                typed.iter().sum::<f64>() / typed.len().max(1) as f64
+            })
+            .collect();
+        let centred = centre(&training, &means);
+        let (_, scores, loadings) = principal_axes(&centred);
+
+        let n = rows.len();
+        let k = components.min(scores.ncols()).min(n.saturating_sub(groups));
+        if k == 0 {
+            return Err("discriminant analysis needs more individuals than groups".into());
+        }
+        let scores = scores.slice(ndarray::s![.., ..k]).to_owned();
+        let loadings = loadings.slice(ndarray::s![.., ..k]).to_owned();
+
+        let mut sizes = vec![0usize; groups];
+        let mut centroids = Array2::<f64>::zeros((groups, k));
+        for (row, label) in scores.genrows().into_iter().zip(labels.iter()) {
+            sizes[*label] += 1;
+            let mut centroid = centroids.row_mut(*label);
+            centroid += &row;
+        }
+        for (mut centroid, size) in centroids.genrows_mut().into_iter().zip(sizes.iter()) {
+            centroid.mapv_inplace(|x| x / *size as f64);
+        }
+
+        let mut within = Array2::<f64>::zeros((k, k));
+        for (row, label) in scores.genrows().into_iter().zip(labels.iter()) {
+            let d = (&row - &centroids.row(*label)).insert_axis(Axis(1));
+            within += &d.dot(&d.t());
+        }
+        within.mapv_inplace(|x| x / (n - groups) as f64);
+        let mut between = Array2::<f64>::zeros((k, k));
+        for (centroid, size) in centroids.genrows().into_iter().zip(sizes.iter()) {
+            let c = centroid.insert_axis(Axis(1));
+            between += &(c.dot(&c.t()) * *size as f64);
+        }
+        between.mapv_inplace(|x| x / (groups - 1) as f64);
+
+        let within = cholesky(&within)
+            .ok_or("the within group covariance is singular, retain fewer principal components")?;
+        // Whiten the between group covariance: L^-1 B L^-T
+        let half = solve_columns(&within, &between);
+        let whitened = solve_columns(&within, &half.t().to_owned());
+        let (values, vectors) = symmetric_eigen(&whitened);
+        let d = (groups - 1).min(k);
+        let mut axes = Array2::<f64>::zeros((k, d));
+        for j in 0..d {
+            axes.column_mut(j)
+                .assign(&backward_substitute(&within, &vectors.column(j).to_owned()));
+        }
+
+        Ok(Model {
+            means,
+            loadings,
+            within,
+            centroids,
+            log_priors: sizes
+                .iter()
+                .map(|size| (*size as f64 / n as f64).ln())
+                .collect(),
+            eigenvalues: values[..d].to_vec(),
+            axes,
+        })
+    }
+
+    /// The principal scores of rows of a frequency matrix
+    fn principal_scores(&self, x: &Array2<f64>) -> Array2<f64> {
+        centre(x, &self.means).dot(&self.loadings)
+    }
+
+    /// The posterior probabilities of membership of one row of principal scores
+    fn posterior(&self, scores: ArrayView1<f64>) -> Vec<f64> {
+        let log_posterior: Vec<f64> = self
+            .centroids
+            .genrows()
+            .into_iter()
+            .zip(self.log_priors.iter())
+            .map(|(centroid, log_prior)| {
+                let y = forward_substitute(&self.within, &(&scores - &centroid));
+                log_prior - y.dot(&y) / 2.0
+            })
+            .collect();
+        let max = log_posterior
+            .iter()
+            .cloned()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let weights: Vec<f64> = log_posterior.iter().map(|lp| (lp - max).exp()).collect();
+        let total: f64 = weights.iter().sum();
+        weights.iter().map(|w| w / total).collect()
+    }
+}
+
+/// Subtracts `means` from every row, placing missing values at zero
+fn centre(x: &Array2<f64>, means: &Array1<f64>) -> Array2<f64> {
+    let mut centred = x - means;
+    centred.mapv_inplace(|x| if x.is_nan() { 0.0 } else { x });
+    centred
+}
+
+/// Solves `L X = B` column by column for a lower triangular `L`
+fn solve_columns(l: &Array2<f64>, b: &Array2<f64>) -> Array2<f64> {
+    let mut x = Array2::<f64>::zeros(b.dim());
+    for (j, column) in b.gencolumns().into_iter().enumerate() {
+        x.column_mut(j)
+            .assign(&forward_substitute(l, &column.to_owned()));
+    }
+    x
+}
+
+/// The frequency matrix, the names of the groups with members and every
+/// individual's prior group
+struct DiscriminantData {
+    x: Array2<f64>,
+    groups: Vec<String>,
+    prior_groups: Vec<Option<usize>>,
+}
+
+impl Sample {
+    fn discriminant_data(&mut self) -> Result<DiscriminantData, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let x = self.matrix.frequency()?.mapv(|f| f as f64);
+        let groups: Vec<String> = self
+            .group_names()
+            .into_iter()
+            .filter(|group| self.individuals.values().any(|i| i.in_group(group)))
+            .cloned()
+            .collect();
+        if groups.len() < 2 {
+            return Err("discriminant analysis needs at least two groups".into());
+        }
+        let prior_groups = self
+            .individuals
+            .values()
+            .map(|individual| groups.iter().position(|group| individual.in_group(group)))
+            .collect();
+        Ok(DiscriminantData {
+            x,
+            groups,
+            prior_groups,
+        })
+    }
+}
+
+impl DiscriminantAnalysis for Sample {
+    fn dapc(&mut self, dapc: &Dapc) -> Result<DapcSummary, Box<dyn Error>> {
+        let DiscriminantData {
+            x,
+            groups,
+            prior_groups,
+        } = self.discriminant_data()?;
+        let (rows, labels): (Vec<usize>, Vec<usize>) = prior_groups
+            .iter()
+            .enumerate()
+            .filter_map(|(row, group)| Some((row, (*group)?)))
+            .unzip();
+        let components = dapc.components.unwrap_or((rows.len() / 3).max(1));
+        let model = Model::fit(&x, &rows, &labels, groups.len(), components)?;
+
+        let principal = model.principal_scores(&x);
+        let mut posterior = Array2::<f32>::zeros((x.nrows(), groups.len()));
+        for (i, scores) in principal.genrows().into_iter().enumerate() {
+            for (g, p) in model.posterior(scores).into_iter().enumerate() {
+                posterior[[i, g]] = p as f32;
+            }
+        }
+
+        Ok(DapcSummary {
+            groups,
+            individual_names: self.matrix.individual_names.clone(),
+            prior_groups,
+            eigenvalues: model.eigenvalues.iter().map(|x| *x as f32).collect(),
+            coefficients: model.axes.mapv(|x| x as f32),
+            allele_coefficients: model.loadings.dot(&model.axes).mapv(|x| x as f32),
+            alleles: self.allele_labels(),
+            scores: principal.dot(&model.axes).mapv(|x| x as f32),
+            posterior,
+        })
+    }
+
+    fn dapc_cross_validation(
+        &mut self,
+        validation: &DapcCrossValidation,
+    ) -> Result<CrossValidationSummary, Box<dyn Error>> {
+        let DiscriminantData {
+            x,
+            groups,
+            prior_groups,
+        } = self.discriminant_data()?;
+        let members: Vec<Vec<usize>> = (0..groups.len())
+            .map(|g| {
+                (0..prior_groups.len())
+                    .filter(|row| prior_groups[*row] == Some(g))
+                    .collect()
+            })
+            .collect();
+
+        let mut rng = self.random.or_seed(validation.seed).rng();
+        let mut splits = vec![];
+        for _ in 0..validation.replicates {
+            let (mut training, mut held_out) = (vec![], vec![]);
+            for rows in members.iter() {
+                let mut rows = rows.clone();
+                rows.shuffle(&mut rng);
+                let n = ((rows.len() as f32 * validation.training).round() as usize)
+                    .clamp(1, rows.len().saturating_sub(1).max(1));
+                training.extend_from_slice(&rows[..n]);
+                held_out.extend_from_slice(&rows[n..]);
+            }
+            splits.push((training, held_out));
+        }
+        if splits.iter().all(|(_, held_out)| held_out.is_empty()) {
+            return Err("cross-validation holds out no individuals".into());
+        }
+
+        // Every fit is capped at its training individuals minus the groups.
+        let smallest = splits.iter().map(|(training, _)| training.len()).min();
+        let most = smallest.unwrap_or(0).saturating_sub(groups.len()).min(x.ncols());
+        let components = validation
+            .components
+            .clone()
+            .unwrap_or_else(|| (1..=most).collect());
+        let mut accuracy = vec![];
+        for k in components.iter() {
+            let (mut correct, mut total) = (0, 0);
+            for (training, held_out) in splits.iter() {
+                let labels: Vec<usize> = training
+                    .iter()
+                    .map(|row| prior_groups[*row].unwrap())
+                    .collect();
+                let model = Model::fit(&x, training, &labels, groups.len(), *k)?;
+                let scores = model.principal_scores(&x.select(Axis(0), held_out));
+                for (row, scores) in held_out.iter().zip(scores.genrows()) {
+                    let posterior = model.posterior(scores);
+                    let assigned = (0..posterior.len()).fold(0, |best, g| {
+                        if posterior[g] > posterior[best] {
+                            g
+                        } else {
+                            best
+                        }
+                    });
+                    if Some(assigned) == prior_groups[*row] {
+                        correct += 1;
+                    }
+                    total += 1;
+                }
+            }
+            accuracy.push(correct as f32 / total as f32);
+        }
+        Ok(CrossValidationSummary {
+            components,
+            accuracy,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_dapc_assigns_distinct_groups() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new().group_field("pop").from_reader(Box::new(
+                "pop,a,b,c\n\
+             x,1/1,1/1,1/2\nx,1/1,1/2,1/1\nx,1/2,1/1,1/1\nx,1/1,1/1,1/1\n\
+             y,2/2,2/2,1/2\ny,2/2,2/3,2/2\ny,2/3,2/2,2/2\ny,2/2,2/2,2/2\n\
+             z,3/3,3/3,3/3\nz,3/3,3/1,3/3\nz,3/1,3/3,3/3\nz,3/3,3/3,3/2"
+                    .as_bytes(),
+            ))?,
+        )?;
+        let ungrouped = ["a", "a", "b", "b", "c", "c"].iter().map(|locus| {
+            Ok(Observation::Allele(
+                "u".into(),
+                locus.to_string(),
+                "1".into(),
+            ))
+        });
+        sample.observe(ungrouped)?;
+
+        let summary = sample.dapc(Dapc::new().components(3))?;
+        assert_eq!(summary.groups(), ["x", "y", "z"]);
+        assert_eq!(summary.eigenvalues().len(), 2);
+        assert_eq!(summary.coefficients().dim(), (3, 2));
+        assert_eq!(
+            summary.allele_coefficients().nrows(),
+            summary.alleles().len()
+        );
+        assert_eq!(summary.accuracy(), 1.0);
+        let ungrouped = summary
+            .individual_names()
+            .iter()
+            .position(|n| n == "u")
+            .unwrap();
+        assert_eq!(summary.prior_groups()[ungrouped], None);
+        assert_eq!(summary.assignments()[ungrouped], "x");
+        for row in summary.posterior().genrows() {
+            assert!((row.sum() - 1.0).abs() < 1e-5);
+        }
+
+        let mut validation = DapcCrossValidation::new();
+        validation
+            .components(vec![1, 2, 3])
+            .training(0.75)
+            .replicates(10)
+            .seed(7);
+        let cross_validation = sample.dapc_cross_validation(&validation)?;
+        assert_eq!(cross_validation.components(), [1, 2, 3]);
+        assert!(cross_validation
+            .accuracy()
+            .iter()
+            .all(|a| (0.0..=1.0).contains(a)));
+        let best = cross_validation.best() - 1;
+        assert!(cross_validation
+            .accuracy()
+            .iter()
+            .all(|a| *a <= cross_validation.accuracy()[best]));
+        assert_eq!(
+            sample.dapc_cross_validation(&validation)?.accuracy(),
+            cross_validation.accuracy()
+        );
+
+        // By default components go up to the 9 training individuals less the 3 groups.
+        let mut validation = DapcCrossValidation::new();
+        validation.training(0.75).replicates(2).seed(7);
+        let cross_validation = sample.dapc_cross_validation(&validation)?;
+        assert_eq!(cross_validation.components(), [1, 2, 3, 4, 5, 6]);
+        Ok(())
+    }
+}
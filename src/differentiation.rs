@@ -0,0 +1,336 @@
+use crate::diversity::{tally, Tally};
+use crate::prelude::*;
+use crate::PairwiseMatrix;
+use std::error::Error;
+
+/// Heterozygosity based differentiation at one `Locus`, or over all loci
+///
+/// Within (`Hs`) and total (`Ht`) heterozygosities are corrected for
+/// sample size following Nei & Chesser (1983) before being combined.
+/// Unlike `Fst`, `G''st` and Jost's `D` reach their maximum of one
+/// whenever groups share no alleles, however polymorphic the locus.
+pub struct LocusDifferentiation {
+    locus: Option<String>,
+    gst: f32,
+    g_prime_st: f32,
+    g_double_prime_st: f32,
+    jost_d: f32,
+}
+
+impl LocusDifferentiation {
+    /// The name of the locus, or `None` for statistics over all loci
+    pub fn locus(&self) -> Option<&str> {
+        self.locus.as_deref()
+    }
+
+    /// Nei's `Gst`
+    pub fn gst(&self) -> f32 {
+        self.gst
+    }
+
+    /// Hedrick's (2005) standardized `G'st`
+    pub fn g_prime_st(&self) -> f32 {
+        self.g_prime_st
+    }
+
+    /// Meirmans & Hedrick's (2011) `G''st`
+    pub fn g_double_prime_st(&self) -> f32 {
+        self.g_double_prime_st
+    }
+
+    /// Jost's (2008) `D`
+    pub fn jost_d(&self) -> f32 {
+        self.jost_d
+    }
+}
+
+/// Differentiation of every `Locus` and over all loci
+///
+/// Over all loci `Gst`, `G'st` and `G''st` are computed from the mean
+/// `Hs`, `Ht` and number of typed groups, while `D` is the harmonic mean
+/// of the per-locus values as recommended by Jost. The harmonic mean is
+/// undefined when a locus has `D <= 0`, in which case `D` also falls back
+/// to these means.
+pub struct DifferentiationSummary {
+    loci: Vec<LocusDifferentiation>,
+    overall: LocusDifferentiation,
+}
+
+impl DifferentiationSummary {
+    /// The statistics of every locus, in the order of the loci
+    ///
+    /// Loci typed in fewer than two groups cannot be estimated and are
+    /// `NaN`. They are left out of the statistics over all loci.
+    pub fn loci(&self) -> &[LocusDifferentiation] {
+        &self.loci
+    }
+
+    /// The statistics over all loci
+    pub fn overall(&self) -> &LocusDifferentiation {
+        &self.overall
+    }
+}
+
+/// Every differentiation estimator over all loci between pairs of `Group`s
+pub struct PairwiseDifferentiation {
+    gst: PairwiseMatrix,
+    g_prime_st: PairwiseMatrix,
+    g_double_prime_st: PairwiseMatrix,
+    jost_d: PairwiseMatrix,
+}
+
+impl PairwiseDifferentiation {
+    pub fn gst(&self) -> &PairwiseMatrix {
+        &self.gst
+    }
+
+    pub fn g_prime_st(&self) -> &PairwiseMatrix {
+        &self.g_prime_st
+    }
+
+    pub fn g_double_prime_st(&self) -> &PairwiseMatrix {
+        &self.g_double_prime_st
+    }
+
+    pub fn jost_d(&self) -> &PairwiseMatrix {
+        &self.jost_d
+    }
+}
+
+pub trait Differentiation {
+    /// Computes differentiation between all `Group`s
+    fn differentiation(&mut self) -> Result<DifferentiationSummary, Box<dyn Error>>;
+
+    /// Computes differentiation between every pair of `Group`s
+    ///
+    /// Pairs of groups that share no typed locus are `NaN`.
+    fn pairwise_differentiation(&mut self) -> Result<PairwiseDifferentiation, Box<dyn Error>>;
+}
+
+/// Bias corrected heterozygosities of one locus
+struct Heterozygosities {
+    hs: f64,
+    ht: f64,
+    groups: f64,
+}
+
+impl Heterozygosities {
+    fn from_tallies(tallies: &[&Tally]) -> Option<Heterozygosities> {
+        let typed: Vec<&&Tally> = tallies.iter().filter(|t| t.individuals > 0).collect();
+        if typed.len() < 2 {
+            return None;
+        }
+        let k = typed.len() as f64;
+        let frequencies: Vec<Vec<f32>> = typed.iter().map(|t| t.frequencies()).collect();
+        let n_harmonic = k / typed.iter().map(|t| 1.0 / t.individuals as f64).sum::<f64>();
+
+        let ho = typed
+            .iter()
+            .map(|t| t.heterozygotes as f64 / t.individuals as f64)
+            .sum::<f64>()
+            / k;
+        let hs = frequencies
+            .iter()
+            .map(|p| 1.0 - p.iter().map(|x| (*x as f64).powf(2.0)).sum::<f64>())
+            .sum::<f64>()
+            / k;
+        let ht = 1.0
+            - (0..frequencies[0].len())
+                .map(|u| (frequencies.iter().map(|p| p[u] as f64).sum::<f64>() / k).powf(2.0))
+                .sum::<f64>();
+
+        let hs = if n_harmonic > 1.0 {
+            n_harmonic / (n_harmonic - 1.0) * (hs - ho / (2.0 * n_harmonic))
+        } else {
+            hs
+        };
+        let ht = ht + hs / (k * n_harmonic) - ho / (2.0 * k * n_harmonic);
+        Some(Heterozygosities { hs, ht, groups: k })
+    }
+
+    fn statistics(&self, locus: Option<String>) -> LocusDifferentiation {
+        let (hs, ht, k) = (self.hs, self.ht, self.groups);
+        let gst = (ht - hs) / ht;
+        LocusDifferentiation {
+            locus,
+            gst: gst as f32,
+            g_prime_st: (gst * (k - 1.0 + hs) / ((k - 1.0) * (1.0 - hs))) as f32,
+            g_double_prime_st: (k * (ht - hs) / ((k * ht - hs) * (1.0 - hs))) as f32,
+            jost_d: ((ht - hs) / (1.0 - hs) * k / (k - 1.0)) as f32,
+        }
+    }
+}
+
+impl Sample {
+    /// Differentiation between the named groups, or `None` when no locus
+    /// is typed in at least two of them
+    fn differentiation_between(
+        &mut self,
+        groups: &[&String],
+    ) -> Result<Option<DifferentiationSummary>, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let tallies: Vec<Vec<Tally>> = groups
+            .iter()
+            .map(|group| tally(&self.matrix, &self.group_rows(group)))
+            .collect();
+        let per_locus: Vec<(String, Option<Heterozygosities>)> = self
+            .loci
+            .keys()
+            .enumerate()
+            .map(|(l, locus)| {
+                let tallies: Vec<&Tally> = tallies.iter().map(|t| &t[l]).collect();
+                (locus.clone(), Heterozygosities::from_tallies(&tallies))
+            })
+            .collect();
+        let loci: Vec<LocusDifferentiation> = per_locus
+            .iter()
+            .map(|(locus, h)| match h {
+                Some(h) => h.statistics(Some(locus.clone())),
+                None => LocusDifferentiation {
+                    locus: Some(locus.clone()),
+                    gst: f32::NAN,
+                    g_prime_st: f32::NAN,
+                    g_double_prime_st: f32::NAN,
+                    jost_d: f32::NAN,
+                },
+            })
+            .collect();
+        let heterozygosities: Vec<&Heterozygosities> =
+            per_locus.iter().filter_map(|(_, h)| h.as_ref()).collect();
+        if heterozygosities.is_empty() {
+            return Ok(None);
+        }
+
+        let n = heterozygosities.len() as f64;
+        let mut overall = Heterozygosities {
+            hs: heterozygosities.iter().map(|h| h.hs).sum::<f64>() / n,
+            ht: heterozygosities.iter().map(|h| h.ht).sum::<f64>() / n,
+            groups: heterozygosities.iter().map(|h| h.groups).sum::<f64>() / n,
+        }
+        .statistics(None);
+        let estimated: Vec<f32> = loci
+            .iter()
+            .map(|l| l.jost_d)
+            .filter(|d| !d.is_nan())
+            .collect();
+        if estimated.iter().all(|d| *d > 0.0) {
+            overall.jost_d =
+                estimated.len() as f32 / estimated.iter().map(|d| 1.0 / d).sum::<f32>();
+        }
+
+        Ok(Some(DifferentiationSummary { loci, overall }))
+    }
+}
+
+impl Differentiation for Sample {
+    fn differentiation(&mut self) -> Result<DifferentiationSummary, Box<dyn Error>> {
+        let groups: Vec<String> = self.group_names().into_iter().cloned().collect();
+        if groups.len() < 2 {
+            return Err("differentiation needs at least two groups".into());
+        }
+        self.differentiation_between(&groups.iter().collect::<Vec<_>>())?
+            .ok_or_else(|| "no locus is typed in at least two groups".into())
+    }
+
+    fn pairwise_differentiation(&mut self) -> Result<PairwiseDifferentiation, Box<dyn Error>> {
+        let labels: Vec<String> = self.group_names().into_iter().cloned().collect();
+        let empty = || PairwiseMatrix {
+            labels: labels.clone(),
+            data: ndarray::Array2::<f32>::zeros((labels.len(), labels.len())),
+        };
+        let mut pairwise = PairwiseDifferentiation {
+            gst: empty(),
+            g_prime_st: empty(),
+            g_double_prime_st: empty(),
+            jost_d: empty(),
+        };
+        for i in 0..labels.len() {
+            for j in (i + 1)..labels.len() {
+                let overall = self
+                    .differentiation_between(&[&labels[i], &labels[j]])?
+                    .map(|summary| summary.overall);
+                let values = match overall {
+                    Some(o) => [o.gst, o.g_prime_st, o.g_double_prime_st, o.jost_d],
+                    None => [f32::NAN; 4],
+                };
+                for (matrix, value) in [
+                    (&mut pairwise.gst, values[0]),
+                    (&mut pairwise.g_prime_st, values[1]),
+                    (&mut pairwise.g_double_prime_st, values[2]),
+                    (&mut pairwise.jost_d, values[3]),
+                ] {
+                    matrix.data[[i, j]] = value;
+                    matrix.data[[j, i]] = value;
+                }
+            }
+        }
+        Ok(pairwise)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_jost_d_is_high_for_private_alleles() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().group_field("pop").from_reader(Box::new(
+            "pop,a,b\nx,1/2,1/2\nx,3/4,1/1\nx,1/3,1/2\ny,5/6,2/2\ny,7/8,1/2\ny,5/7,1/1".as_bytes(),
+        ))?)?;
+
+        let summary = sample.differentiation()?;
+        let a = &summary.loci()[0];
+        let b = &summary.loci()[1];
+        assert_eq!(a.locus(), Some("a"));
+        assert!(a.jost_d() > 0.9);
+        assert!(a.g_double_prime_st() > 0.9);
+        assert!(a.gst() < a.jost_d());
+        assert!(b.jost_d() < 0.1);
+
+        let pairwise = sample.pairwise_differentiation()?;
+        assert_eq!(pairwise.jost_d().get("x", "y"), Some(summary.overall().jost_d()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_untyped_groups() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .group_field("pop")
+                .missing_codes(["?"].iter().map(|s| s.to_string()).collect())
+                .from_reader(Box::new(
+                    "pop,a,b\nx,1/2,1/2\nx,3/4,1/1\ny,5/6,2/2\ny,7/8,1/2\nz,?,1/1\nz,?,1/2\nw,1/3,?\nw,2/2,?"
+                        .as_bytes(),
+                ))?,
+        )?;
+        let pairwise = sample.pairwise_differentiation()?;
+        assert!(pairwise.gst().get("z", "w").unwrap().is_nan());
+        assert!(!pairwise.gst().get("x", "z").unwrap().is_nan());
+
+        // A single locus typed in two of three groups is its own overall estimate.
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .group_field("pop")
+                .missing_codes(["?"].iter().map(|s| s.to_string()).collect())
+                .from_reader(Box::new(
+                    "pop,a,b\nx,1/2,1/1\nx,3/4,1/2\ny,5/6,?\ny,1/1,?\nz,?,?\nz,?,?".as_bytes(),
+                ))?,
+        )?;
+        let summary = sample.differentiation()?;
+        let (locus, overall) = (&summary.loci()[0], summary.overall());
+        assert_eq!(locus.g_prime_st(), overall.g_prime_st());
+        assert_eq!(locus.jost_d(), overall.jost_d());
+
+        // A locus typed in a single group is kept, by name, as NaN.
+        let untyped = &summary.loci()[1];
+        assert_eq!(untyped.locus(), Some("b"));
+        assert!(untyped.gst().is_nan() && untyped.jost_d().is_nan());
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+use crate::prelude::*;
+use crate::{AlleleMatrix, PairwiseMatrix};
+use std::error::Error;
+
+/// A measure of genetic dissimilarity between two individuals
+///
+/// Implementations compare two rows of an `AlleleMatrix`. Loci where
+/// either individual carries no alleles should be left out of the
+/// comparison.
+pub trait Dissimilarity {
+    /// The dissimilarity between rows `a` and `b` of `matrix`
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32;
+}
+
+/// The allele frequencies of both rows at every locus typed in both
+fn typed_loci(
+    matrix: &AlleleMatrix,
+    a: usize,
+    b: usize,
+) -> Vec<(ndarray::Array1<f32>, ndarray::Array1<f32>)> {
+    (0..matrix.loci().len())
+        .filter_map(|locus| {
+            Some((
+                matrix.locus_frequency(a, locus)?,
+                matrix.locus_frequency(b, locus)?,
+            ))
+        })
+        .collect()
+}
+
+/// Prevosti's distance, the mean over loci of half the absolute
+/// difference in allele frequencies
+pub struct Prevosti;
+
+impl Dissimilarity for Prevosti {
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32 {
+        let loci = typed_loci(matrix, a, b);
+        loci.iter()
+            .map(|(x, y)| (x - y).map(|d| d.abs()).sum() / 2.0)
+            .sum::<f32>()
+            / loci.len() as f32
+    }
+}
+
+/// Nei's (1972) standard distance, `-ln(Jxy / sqrt(Jx Jy))`
+pub struct Nei;
+
+impl Dissimilarity for Nei {
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32 {
+        let (mut jxy, mut jx, mut jy) = (0.0, 0.0, 0.0);
+        for (x, y) in typed_loci(matrix, a, b) {
+            jxy += (&x * &y).sum();
+            jx += (&x * &x).sum();
+            jy += (&y * &y).sum();
+        }
+        -(jxy / (jx * jy).sqrt()).ln()
+    }
+}
+
+/// The Euclidean distance between allele frequencies
+pub struct Euclidean;
+
+impl Dissimilarity for Euclidean {
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32 {
+        typed_loci(matrix, a, b)
+            .iter()
+            .map(|(x, y)| (x - y).map(|d| d * d).sum())
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+/// One minus the proportion of alleles shared at each locus, averaged over loci
+///
+/// For individuals of equal ploidy this equals Prevosti's distance.
+pub struct AlleleSharing;
+
+impl Dissimilarity for AlleleSharing {
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32 {
+        let loci = typed_loci(matrix, a, b);
+        1.0 - loci
+            .iter()
+            .map(|(x, y)| x.iter().zip(y.iter()).map(|(x, y)| x.min(*y)).sum::<f32>())
+            .sum::<f32>()
+            / loci.len() as f32
+    }
+}
+
+/// Bruvo et al.'s (2004) stepwise mutation aware distance for microsatellites
+///
+/// Allele sizes are read from the `Variation` names of microsatellite
+/// loci and divided by the repeat length to give a number of repeat units.
+/// Two alleles `x` repeats apart are `1 - 2^-|x|` apart, and the distance
+/// at a locus is the mean over the pairing of alleles that minimises it.
+/// Alleles without a size, including every allele of a `Classical` or
+/// `Snp` locus, are 0 apart when identical and 1 apart otherwise, as are
+/// the unmatched alleles of individuals of differing ploidy. Between
+/// individuals of equal ploidy this makes the distance at such loci that
+/// of `Prevosti`.
+pub struct Bruvo {
+    repeat: Option<f32>,
+}
+
+impl Default for Bruvo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bruvo {
+    /// Construct a Bruvo distance dividing sizes by the annotated repeat
+    /// length of each locus, and taking sizes at loci without one to be
+    /// given in repeat units already
+    pub fn new() -> Self {
+        Self { repeat: None }
+    }
+
+    /// Construct a Bruvo distance for sizes given in base pairs, with the
+    /// same repeat length at every locus
+    pub fn with_repeat(repeat: f32) -> Self {
+        Self { repeat: Some(repeat) }
+    }
+
+    /// The repeat length of the `locus`th locus of the matrix
+    fn repeat(&self, matrix: &AlleleMatrix, locus: usize) -> f32 {
+        self.repeat.unwrap_or_else(|| {
+            matrix
+                .repeat_lengths()
+                .get(locus)
+                .copied()
+                .flatten()
+                .map_or(1.0, |length| length as f32)
+        })
+    }
+
+    fn allele_distance(&self, matrix: &AlleleMatrix, repeat: f32, x: Option<usize>, y: Option<usize>) -> f32 {
+        match (x, y) {
+            (Some(x), Some(y)) if x == y => 0.0,
+            (Some(x), Some(y)) => {
+                let size = |column: usize| matrix.allele_sizes().get(column).copied().flatten();
+                match (size(x), size(y)) {
+                    (Some(x), Some(y)) => 1.0 - 2f32.powf(-((x - y) / repeat).abs()),
+                    _ => 1.0,
+                }
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// The smallest total cost of pairing every row of a square matrix with a
+/// distinct column
+///
+/// This is the Hungarian algorithm, which takes `O(n^3)` time rather than
+/// the `O(n!)` of trying every pairing, so high ploidies stay tractable.
+fn min_assignment(costs: &[Vec<f32>]) -> f32 {
+    let n = costs.len();
+    // Potentials of rows `u` and columns `v`, and the row assigned to every
+    // column, all indexed from one with zero as a sentinel.
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; n + 1];
+    let mut assigned = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+    for row in 1..=n {
+        assigned[0] = row;
+        let mut column = 0;
+        let mut slack = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[column] = true;
+            let current = assigned[column];
+            let mut delta = f64::INFINITY;
+            let mut next = 0;
+            for j in 1..=n {
+                if !used[j] {
+                    let reduced = costs[current - 1][j - 1] as f64 - u[current] - v[j];
+                    if reduced < slack[j] {
+                        slack[j] = reduced;
+                        way[j] = column;
+                    }
+                    if slack[j] < delta {
+                        delta = slack[j];
+                        next = j;
+                    }
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    u[assigned[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    slack[j] -= delta;
+                }
+            }
+            column = next;
+            if assigned[column] == 0 {
+                break;
+            }
+        }
+        while column != 0 {
+            let previous = way[column];
+            assigned[column] = assigned[previous];
+            column = previous;
+        }
+    }
+    (1..=n)
+        .map(|j| costs[assigned[j] - 1][j - 1])
+        .sum()
+}
+
+impl Dissimilarity for Bruvo {
+    fn dissimilarity(&self, matrix: &AlleleMatrix, a: usize, b: usize) -> f32 {
+        let alleles = |row: usize, (start, end): (usize, usize)| -> Vec<Option<usize>> {
+            (start..end)
+                .flat_map(|column| std::iter::repeat_n(Some(column), matrix.data()[[row, column]] as usize))
+                .collect()
+        };
+
+        let mut total = 0.0;
+        let mut typed = 0;
+        for (i, locus) in matrix.loci().iter().enumerate() {
+            let repeat = self.repeat(matrix, i);
+            let mut x = alleles(a, *locus);
+            let mut y = alleles(b, *locus);
+            if x.is_empty() || y.is_empty() {
+                continue;
+            }
+            let ploidy = x.len().max(y.len());
+            x.resize(ploidy, None);
+            y.resize(ploidy, None);
+            let costs: Vec<Vec<f32>> = x
+                .iter()
+                .map(|x| y.iter().map(|y| self.allele_distance(matrix, repeat, *x, *y)).collect())
+                .collect();
+            total += min_assignment(&costs) / ploidy as f32;
+            typed += 1;
+        }
+        total / typed as f32
+    }
+}
+
+pub trait IndividualDistance {
+    /// Computes the dissimilarity between every pair of individuals
+    fn distance_matrix<D: Dissimilarity>(&mut self, measure: &D) -> Result<PairwiseMatrix, Box<dyn Error>>;
+}
+
+impl IndividualDistance for Sample {
+    fn distance_matrix<D: Dissimilarity>(&mut self, measure: &D) -> Result<PairwiseMatrix, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let n = self.matrix.individuals();
+        let mut data = ndarray::Array2::<f32>::zeros((n, n));
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let d = measure.dissimilarity(&self.matrix, i, j);
+                data[[i, j]] = d;
+                data[[j, i]] = d;
+            }
+        }
+        Ok(PairwiseMatrix {
+            labels: self.matrix.individual_names.clone(),
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().name_field("name").from_reader(Box::new(
+            "name,a,b\nx,10/12,1/1\ny,10/13,1/1\nz,20/22,2/2".as_bytes(),
+        ))?)?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_prevosti_and_allele_sharing_agree() -> Result<(), Box<dyn Error>> {
+        let mut sample = sample()?;
+        let prevosti = sample.distance_matrix(&Prevosti)?;
+        let sharing = sample.distance_matrix(&AlleleSharing)?;
+        assert_eq!(prevosti.labels(), ["x", "y", "z"]);
+        assert!((prevosti.get("x", "y").unwrap() - 0.25).abs() < 1e-6);
+        assert!((prevosti.get("x", "z").unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(prevosti.data(), sharing.data());
+        assert!(sample.distance_matrix(&Nei)?.get("x", "z").unwrap().is_infinite());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bruvo_is_repeat_aware() -> Result<(), Box<dyn Error>> {
+        let bruvo = sample()?.distance_matrix(&Bruvo::new())?;
+        // Locus a pairs 10-10 and 12-13, locus b is identical.
+        assert!((bruvo.get("x", "y").unwrap() - 0.25 / 2.0).abs() < 1e-6);
+        assert!(bruvo.get("x", "y").unwrap() < bruvo.get("x", "z").unwrap());
+
+        // With a repeat length of 2 at locus a, 12 and 13 are half a repeat apart.
+        let mut sample = sample()?;
+        sample._observe(Observation::Annotation("a".to_string(), Annotation::RepeatLength(2)));
+        let bruvo = sample.distance_matrix(&Bruvo::new())?;
+        let expected = (1.0 - 2f32.powf(-0.5)) / 2.0 / 2.0;
+        assert!((bruvo.get("x", "y").unwrap() - expected).abs() < 1e-6);
+
+        // Alleles of a classical locus are only identical or different.
+        sample.set_locus_hint("a", LocusHint::Classical);
+        let bruvo = sample.distance_matrix(&Bruvo::new())?;
+        let prevosti = sample.distance_matrix(&Prevosti)?;
+        assert!((bruvo.get("x", "y").unwrap() - prevosti.get("x", "y").unwrap()).abs() < 1e-6);
+
+        // Alleles of a matrix without sizes or names are unknown in size.
+        let matrix = AlleleMatrix::from_vec(2, vec![(0, 2)], vec![2, 0, 0, 2])?;
+        assert_eq!(Bruvo::new().dissimilarity(&matrix, 0, 1), 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_assignment_finds_the_best_pairing() {
+        fn brute_force(costs: &[Vec<f32>], row: usize, used: &mut Vec<bool>) -> f32 {
+            if row == costs.len() {
+                return 0.0;
+            }
+            let mut best = f32::INFINITY;
+            for column in 0..used.len() {
+                if !used[column] {
+                    used[column] = true;
+                    best = best.min(costs[row][column] + brute_force(costs, row + 1, used));
+                    used[column] = false;
+                }
+            }
+            best
+        }
+        for n in 1..7 {
+            let costs: Vec<Vec<f32>> = (0..n)
+                .map(|i| (0..n).map(|j| ((i * 7 + j * 13 + i * j) % 11) as f32 / 11.0).collect())
+                .collect();
+            let expected = brute_force(&costs, 0, &mut vec![false; n]);
+            assert!((min_assignment(&costs) - expected).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::distributions::ln_factorial;
+use crate::prelude::*;
+use crate::{AlleleCount, AlleleMatrix};
+use std::error::Error;
+use std::io::Write;
+
+/// Genetic diversity of one population at one `Locus`
+pub struct LocusDiversity {
+    locus: String,
+    individuals: usize,
+    n_alleles: usize,
+    effective_alleles: f32,
+    allelic_richness: f32,
+    observed_heterozygosity: f32,
+    expected_heterozygosity: f32,
+}
+
+impl LocusDiversity {
+    pub fn locus(&self) -> &str {
+        &self.locus
+    }
+
+    /// The number of individuals typed at this locus
+    pub fn individuals(&self) -> usize {
+        self.individuals
+    }
+
+    /// The number of variations present in the population
+    pub fn n_alleles(&self) -> usize {
+        self.n_alleles
+    }
+
+    /// The effective number of alleles, `1 / sum(p^2)`
+    pub fn effective_alleles(&self) -> f32 {
+        self.effective_alleles
+    }
+
+    /// The number of alleles expected in a rarefied subsample
+    ///
+    /// Populations are rarefied to the smallest number of allele copies
+    /// typed at this locus in any population of the same summary, so
+    /// that allelic richness can be compared between them.
+    pub fn allelic_richness(&self) -> f32 {
+        self.allelic_richness
+    }
+
+    /// The proportion of typed individuals that are heterozygous, `Ho`
+    pub fn observed_heterozygosity(&self) -> f32 {
+        self.observed_heterozygosity
+    }
+
+    /// Nei's unbiased gene diversity, `He`
+    pub fn expected_heterozygosity(&self) -> f32 {
+        self.expected_heterozygosity
+    }
+}
+
+/// Genetic diversity of one population at every `Locus`
+pub struct DiversitySummary {
+    population: Option<String>,
+    loci: Vec<LocusDiversity>,
+}
+
+impl DiversitySummary {
+    /// The `Group` summarized, or `None` for the whole `Sample`
+    pub fn population(&self) -> Option<&str> {
+        self.population.as_deref()
+    }
+
+    pub fn loci(&self) -> &[LocusDiversity] {
+        &self.loci
+    }
+
+    pub fn mean_observed_heterozygosity(&self) -> f32 {
+        self.mean(|locus| locus.observed_heterozygosity)
+    }
+
+    pub fn mean_expected_heterozygosity(&self) -> f32 {
+        self.mean(|locus| locus.expected_heterozygosity)
+    }
+
+    pub fn mean_allelic_richness(&self) -> f32 {
+        self.mean(|locus| locus.allelic_richness)
+    }
+
+    fn mean(&self, statistic: fn(&LocusDiversity) -> f32) -> f32 {
+        self.loci.iter().map(statistic).sum::<f32>() / self.loci.len() as f32
+    }
+
+    /// Writes one comma separated row per locus, preceded by a header
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "population",
+            "locus",
+            "individuals",
+            "n_alleles",
+            "effective_alleles",
+            "allelic_richness",
+            "observed_heterozygosity",
+            "expected_heterozygosity",
+        ])?;
+        for locus in self.loci.iter() {
+            wtr.write_record(&[
+                self.population.clone().unwrap_or_default(),
+                locus.locus.clone(),
+                locus.individuals.to_string(),
+                locus.n_alleles.to_string(),
+                locus.effective_alleles.to_string(),
+                locus.allelic_richness.to_string(),
+                locus.observed_heterozygosity.to_string(),
+                locus.expected_heterozygosity.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+pub trait Diversity {
+    /// Summarizes diversity of the whole `Sample` followed by every `Group`
+    fn diversity(&mut self) -> Result<Vec<DiversitySummary>, Box<dyn Error>>;
+}
+
+/// The raw per-locus tallies of one population
+pub(crate) struct Tally {
+    /// Individuals carrying at least one allele at the locus
+    pub(crate) individuals: usize,
+    /// Individuals carrying more than one variation at the locus
+    pub(crate) heterozygotes: usize,
+    /// Copies of each variation
+    pub(crate) counts: Vec<AlleleCount>,
+    /// Heterozygous individuals carrying each variation
+    pub(crate) heterozygous_carriers: Vec<usize>,
+}
+
+impl Tally {
+    /// The number of allele copies typed
+    pub(crate) fn copies(&self) -> AlleleCount {
+        self.counts.iter().sum()
+    }
+
+    /// The frequency of each variation among the typed allele copies
+    pub(crate) fn frequencies(&self) -> Vec<f32> {
+        let copies = self.copies() as f32;
+        self.counts.iter().map(|count| *count as f32 / copies).collect()
+    }
+}
+
+/// Tallies every locus of the matrix over `rows`
+pub(crate) fn tally(matrix: &AlleleMatrix, rows: &[usize]) -> Vec<Tally> {
+    matrix
+        .loci
+        .iter()
+        .map(|(start, end)| {
+            let mut tally = Tally {
+                individuals: 0,
+                heterozygotes: 0,
+                counts: vec![0; end - start],
+                heterozygous_carriers: vec![0; end - start],
+            };
+            for row in rows.iter() {
+                let genotype = matrix.data.slice(ndarray::s![*row, *start..*end]);
+                if genotype.sum() == 0 {
+                    continue;
+                }
+                tally.individuals += 1;
+                let heterozygous = genotype.iter().filter(|count| **count > 0).count() > 1;
+                if heterozygous {
+                    tally.heterozygotes += 1;
+                }
+                for (i, count) in genotype.iter().enumerate() {
+                    tally.counts[i] += count;
+                    if heterozygous && *count > 0 {
+                        tally.heterozygous_carriers[i] += 1;
+                    }
+                }
+            }
+            tally
+        })
+        .collect()
+}
+
+impl Diversity for Sample {
+    fn diversity(&mut self) -> Result<Vec<DiversitySummary>, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+
+        let mut populations: Vec<(Option<String>, Vec<usize>)> =
+            vec![(None, (0..self.matrix.individuals()).collect())];
+        for group in self.group_names() {
+            populations.push((Some(group.clone()), self.group_rows(group)));
+        }
+
+        let loci: Vec<String> = self.loci.keys().cloned().collect();
+        let mut tallies: Vec<Vec<Tally>> = vec![];
+        let mut frequencies = vec![];
+        for (_, rows) in populations.iter() {
+            frequencies.push(self.matrix.population_frequency(rows)?);
+            tallies.push(tally(&self.matrix, rows));
+        }
+
+        let mut summaries = vec![];
+        for (p, (population, _)) in populations.into_iter().enumerate() {
+            let mut summary = DiversitySummary {
+                population,
+                loci: vec![],
+            };
+            for (l, (start, end)) in self.matrix.loci.iter().enumerate() {
+                let tally = &tallies[p][l];
+                let rarefaction = tallies
+                    .iter()
+                    .map(|tallies| tallies[l].copies())
+                    .filter(|copies| *copies > 0)
+                    .min()
+                    .unwrap_or(0);
+                let copies = tally.copies() as f32;
+                let homozygosity: f32 = frequencies[p]
+                    .slice(ndarray::s![*start..*end])
+                    .iter()
+                    .filter(|f| !f.is_nan())
+                    .map(|f| f * f)
+                    .sum();
+                summary.loci.push(LocusDiversity {
+                    locus: loci[l].clone(),
+                    individuals: tally.individuals,
+                    n_alleles: tally.counts.iter().filter(|count| **count > 0).count(),
+                    effective_alleles: 1.0 / homozygosity,
+                    allelic_richness: allelic_richness(&tally.counts, rarefaction),
+                    observed_heterozygosity: tally.heterozygotes as f32 / tally.individuals as f32,
+                    expected_heterozygosity: copies / (copies - 1.0) * (1.0 - homozygosity),
+                });
+            }
+            summaries.push(summary);
+        }
+        Ok(summaries)
+    }
+}
+
+/// The expected number of alleles in a subsample of `size` allele copies
+fn allelic_richness(counts: &[AlleleCount], size: AlleleCount) -> f32 {
+    let total: AlleleCount = counts.iter().sum();
+    if size == 0 || total < size {
+        return f32::NAN;
+    }
+    let ln_choose = |n: AlleleCount, k: AlleleCount| {
+        ln_factorial(n as usize) - ln_factorial(k as usize) - ln_factorial((n - k) as usize)
+    };
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            if total - count < size {
+                1.0
+            } else {
+                1.0 - (ln_choose(total - count, size) - ln_choose(total, size)).exp()
+            }
+        })
+        .sum::<f64>() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_diversity_of_sample_and_groups() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .group_field("pop")
+                .from_reader(Box::new(
+                    "pop,a\nx,1/2\nx,1/2\nx,1/1\ny,3/3\ny,3/3\ny,3/3".as_bytes(),
+                ))?,
+        )?;
+        let summaries = sample.diversity()?;
+        assert_eq!(summaries.len(), 3);
+
+        let all = &summaries[0].loci()[0];
+        assert_eq!(all.n_alleles(), 3);
+        assert!((all.observed_heterozygosity() - 2.0 / 6.0).abs() < 1e-6);
+
+        let x = &summaries[1];
+        assert_eq!(x.population(), Some("x"));
+        let a = &x.loci()[0];
+        assert_eq!(a.individuals(), 3);
+        assert_eq!(a.n_alleles(), 2);
+        // p = (2/3, 1/3), 2n = 6
+        assert!((a.expected_heterozygosity() - 6.0 / 5.0 * (4.0 / 9.0)).abs() < 1e-6);
+        assert!((a.effective_alleles() - 9.0 / 5.0).abs() < 1e-5);
+        assert!((a.allelic_richness() - 2.0).abs() < 1e-5);
+
+        let y = &summaries[2].loci()[0];
+        assert_eq!(y.observed_heterozygosity(), 0.0);
+        assert_eq!(y.expected_heterozygosity(), 0.0);
+        Ok(())
+    }
+}
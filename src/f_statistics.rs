@@ -0,0 +1,327 @@
+use crate::diversity::{tally, Tally};
+use crate::prelude::*;
+use crate::resampling::{Resample, Resampling, ResamplingSummary};
+use crate::PairwiseMatrix;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::ops::Add;
+
+/// F-statistics at one `Locus`, or over all loci
+///
+/// `fis`, `fst` and `fit` are Wright's F-statistics computed from Nei's
+/// observed and expected heterozygosities. `theta`, `wc_fit` and `wc_fis`
+/// are the corresponding estimators of Weir & Cockerham (1984), which
+/// correct for the number and size of the sampled `Group`s.
+pub struct LocusFStatistics {
+    locus: Option<String>,
+    fis: f32,
+    fst: f32,
+    fit: f32,
+    theta: f32,
+    wc_fit: f32,
+    wc_fis: f32,
+}
+
+impl LocusFStatistics {
+    /// The name of the locus, or `None` for statistics over all loci
+    pub fn locus(&self) -> Option<&str> {
+        self.locus.as_deref()
+    }
+
+    pub fn fis(&self) -> f32 {
+        self.fis
+    }
+
+    pub fn fst(&self) -> f32 {
+        self.fst
+    }
+
+    pub fn fit(&self) -> f32 {
+        self.fit
+    }
+
+    /// Weir & Cockerham's estimator of `Fst`
+    pub fn theta(&self) -> f32 {
+        self.theta
+    }
+
+    /// Weir & Cockerham's estimator of `Fit`, their `F`
+    pub fn wc_fit(&self) -> f32 {
+        self.wc_fit
+    }
+
+    /// Weir & Cockerham's estimator of `Fis`, their `f`
+    pub fn wc_fis(&self) -> f32 {
+        self.wc_fis
+    }
+}
+
+/// F-statistics of every `Locus` and over all loci
+pub struct FStatisticsSummary {
+    loci: Vec<LocusFStatistics>,
+    overall: LocusFStatistics,
+    resampling: Option<[ResamplingSummary; 3]>,
+}
+
+impl FStatisticsSummary {
+    pub fn loci(&self) -> &[LocusFStatistics] {
+        &self.loci
+    }
+
+    /// The statistics over all loci
+    pub fn overall(&self) -> &LocusFStatistics {
+        &self.overall
+    }
+
+    /// The resampled `theta` over all loci, if a `Resampling` was given
+    pub fn theta_resampling(&self) -> Option<&ResamplingSummary> {
+        self.resampling.as_ref().map(|resampling| &resampling[0])
+    }
+
+    /// The resampled `wc_fit` over all loci, if a `Resampling` was given
+    pub fn wc_fit_resampling(&self) -> Option<&ResamplingSummary> {
+        self.resampling.as_ref().map(|resampling| &resampling[1])
+    }
+
+    /// The resampled `wc_fis` over all loci, if a `Resampling` was given
+    pub fn wc_fis_resampling(&self) -> Option<&ResamplingSummary> {
+        self.resampling.as_ref().map(|resampling| &resampling[2])
+    }
+}
+
+pub trait FStatistics {
+    /// Computes F-statistics between all `Group`s
+    ///
+    /// Giving a `Resampling` also resamples Weir & Cockerham's estimators
+    /// over all loci, such as by bootstrapping loci.
+    fn f_statistics(
+        &mut self,
+        resampling: Option<&Resampling>,
+    ) -> Result<FStatisticsSummary, Box<dyn Error>>;
+
+    /// Computes Weir & Cockerham's `theta` between every pair of `Group`s
+    fn pairwise_fst(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>>;
+}
+
+/// The per-locus quantities the statistics are ratios of
+///
+/// Summing components over loci gives the multilocus estimators.
+#[derive(Clone, Copy, Default)]
+struct Components {
+    ho: f64,
+    hs: f64,
+    ht: f64,
+    a: f64,
+    b: f64,
+    c: f64,
+}
+
+impl Add for Components {
+    type Output = Components;
+
+    fn add(self, other: Components) -> Components {
+        Components {
+            ho: self.ho + other.ho,
+            hs: self.hs + other.hs,
+            ht: self.ht + other.ht,
+            a: self.a + other.a,
+            b: self.b + other.b,
+            c: self.c + other.c,
+        }
+    }
+}
+
+impl Components {
+    fn from_tallies(tallies: &[&Tally]) -> Components {
+        let typed: Vec<&&Tally> = tallies.iter().filter(|t| t.individuals > 0).collect();
+        let r = typed.len() as f64;
+        let mut components = Components::default();
+        if typed.len() < 2 {
+            return components;
+        }
+
+        let frequencies: Vec<Vec<f32>> = typed.iter().map(|t| t.frequencies()).collect();
+        let n_alleles = frequencies[0].len();
+
+        // Nei's heterozygosities
+        components.ho = typed
+            .iter()
+            .map(|t| t.heterozygotes as f64 / t.individuals as f64)
+            .sum::<f64>()
+            / r;
+        components.hs = frequencies
+            .iter()
+            .map(|p| 1.0 - p.iter().map(|x| (*x as f64).powf(2.0)).sum::<f64>())
+            .sum::<f64>()
+            / r;
+        components.ht = 1.0
+            - (0..n_alleles)
+                .map(|u| (frequencies.iter().map(|p| p[u] as f64).sum::<f64>() / r).powf(2.0))
+                .sum::<f64>();
+
+        // Weir & Cockerham's variance components
+        let n: Vec<f64> = typed.iter().map(|t| t.individuals as f64).collect();
+        let n_total: f64 = n.iter().sum();
+        let n_bar = n_total / r;
+        let n_c = (n_total - n.iter().map(|x| x * x).sum::<f64>() / n_total) / (r - 1.0);
+        if n_bar <= 1.0 {
+            return components;
+        }
+        let columns: Vec<Vec<f64>> = (0..n_alleles)
+            .map(|u| frequencies.iter().map(|p| p[u] as f64).collect())
+            .collect();
+        for (u, p) in columns.iter().enumerate() {
+            let p_bar = n.iter().zip(p.iter()).map(|(n, p)| n * p).sum::<f64>() / n_total;
+            let s2 = n
+                .iter()
+                .zip(p.iter())
+                .map(|(n, p)| n * (p - p_bar).powf(2.0))
+                .sum::<f64>()
+                / ((r - 1.0) * n_bar);
+            let h_bar = typed
+                .iter()
+                .map(|t| t.heterozygous_carriers[u] as f64)
+                .sum::<f64>()
+                / n_total;
+            let pq = p_bar * (1.0 - p_bar);
+            components.a +=
+                n_bar / n_c * (s2 - (pq - (r - 1.0) / r * s2 - h_bar / 4.0) / (n_bar - 1.0));
+            components.b += n_bar / (n_bar - 1.0)
+                * (pq - (r - 1.0) / r * s2 - (2.0 * n_bar - 1.0) / (4.0 * n_bar) * h_bar);
+            components.c += h_bar / 2.0;
+        }
+        components
+    }
+
+    fn statistics(&self, locus: Option<String>) -> LocusFStatistics {
+        let total = self.a + self.b + self.c;
+        LocusFStatistics {
+            locus,
+            fis: (1.0 - self.ho / self.hs) as f32,
+            fst: (1.0 - self.hs / self.ht) as f32,
+            fit: (1.0 - self.ho / self.ht) as f32,
+            theta: (self.a / total) as f32,
+            wc_fit: (1.0 - self.c / total) as f32,
+            wc_fis: (1.0 - self.c / (self.b + self.c)) as f32,
+        }
+    }
+}
+
+impl Sample {
+    /// The components of every locus between the named groups
+    fn f_components(&mut self, groups: &[&String]) -> Result<Vec<Components>, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let tallies: Vec<Vec<Tally>> = groups
+            .iter()
+            .map(|group| tally(&self.matrix, &self.group_rows(group)))
+            .collect();
+        Ok((0..self.matrix.loci.len())
+            .map(|l| Components::from_tallies(&tallies.iter().map(|t| &t[l]).collect::<Vec<_>>()))
+            .collect())
+    }
+}
+
+impl FStatistics for Sample {
+    fn f_statistics(
+        &mut self,
+        resampling: Option<&Resampling>,
+    ) -> Result<FStatisticsSummary, Box<dyn Error>> {
+        let groups: Vec<String> = self.group_names().into_iter().cloned().collect();
+        if groups.len() < 2 {
+            return Err("F-statistics need at least two groups".into());
+        }
+        let components = self.f_components(&groups.iter().collect::<Vec<_>>())?;
+        let overall = components.iter().fold(Components::default(), |sum, c| sum + *c);
+
+        let resampling = match resampling {
+            Some(resampling) => {
+                let summaries = self.resample_many(resampling, |sample: &mut Sample| {
+                    let overall = sample.f_statistics(None)?.overall;
+                    Ok(vec![overall.theta(), overall.wc_fit(), overall.wc_fis()])
+                })?;
+                Some(
+                    <[ResamplingSummary; 3]>::try_from(summaries)
+                        .map_err(|_| "F-statistics resample theta, wc_fit and wc_fis together")?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(FStatisticsSummary {
+            loci: self
+                .loci
+                .keys()
+                .zip(components.iter())
+                .map(|(locus, c)| c.statistics(Some(locus.clone())))
+                .collect(),
+            overall: overall.statistics(None),
+            resampling,
+        })
+    }
+
+    fn pairwise_fst(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>> {
+        let labels: Vec<String> = self.group_names().into_iter().cloned().collect();
+        let mut data = ndarray::Array2::<f32>::zeros((labels.len(), labels.len()));
+        for i in 0..labels.len() {
+            for j in (i + 1)..labels.len() {
+                let theta = self
+                    .f_components(&[&labels[i], &labels[j]])?
+                    .into_iter()
+                    .fold(Components::default(), |sum, c| sum + c)
+                    .statistics(None)
+                    .theta;
+                data[[i, j]] = theta;
+                data[[j, i]] = theta;
+            }
+        }
+        Ok(PairwiseMatrix { labels, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+    use crate::resampling::ResamplingUnit;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().group_field("pop").from_reader(Box::new(
+            "pop,a,b\nx,1/1,1/2\nx,1/1,2/2\nx,1/2,1/1\ny,2/2,1/2\ny,2/2,2/2\ny,2/3,1/1\nz,1/1,1/2\nz,1/1,1/1"
+                .as_bytes(),
+        ))?)?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_f_statistics_detect_differentiated_locus() -> Result<(), Box<dyn Error>> {
+        let mut bootstrap = Resampling::new(ResamplingUnit::Loci);
+        bootstrap.replicates(100).seed(1);
+        let summary = sample()?.f_statistics(Some(&bootstrap))?;
+        let a = &summary.loci()[0];
+        let b = &summary.loci()[1];
+        assert_eq!(a.locus(), Some("a"));
+        assert!(a.theta() > 0.5);
+        assert!(a.fst() > b.fst());
+        assert!(b.theta() < 0.0);
+        let theta = summary.theta_resampling().unwrap();
+        assert_eq!(theta.estimate(), summary.overall().theta());
+        let (lower, upper) = theta.interval();
+        assert!(lower <= upper);
+        let wc_fis = summary.wc_fis_resampling().unwrap();
+        assert_eq!(theta.replicates().len(), wc_fis.replicates().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pairwise_fst_is_symmetric() -> Result<(), Box<dyn Error>> {
+        let matrix = sample()?.pairwise_fst()?;
+        assert_eq!(matrix.labels(), ["x", "y", "z"]);
+        assert_eq!(matrix.get("x", "y"), matrix.get("y", "x"));
+        assert_eq!(matrix.get("x", "x"), Some(0.0));
+        assert!(matrix.get("x", "y").unwrap() > matrix.get("x", "z").unwrap());
+        Ok(())
+    }
+}
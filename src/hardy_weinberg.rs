@@ -0,0 +1,329 @@
+use crate::distributions::{chi_square_survival, ln_factorial};
+use crate::prelude::*;
+use rand::seq::SliceRandom;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The observed and expected number of individuals with one diploid genotype
+pub struct GenotypeCount {
+    alleles: (String, String),
+    observed: u32,
+    expected: f32,
+}
+
+impl GenotypeCount {
+    /// The names of the two variations making up this genotype
+    pub fn alleles(&self) -> (&str, &str) {
+        (&self.alleles.0, &self.alleles.1)
+    }
+
+    /// The number of individuals observed with this genotype
+    pub fn observed(&self) -> u32 {
+        self.observed
+    }
+
+    /// The number of individuals expected with this genotype under HWE
+    pub fn expected(&self) -> f32 {
+        self.expected
+    }
+}
+
+/// The Hardy-Weinberg equilibrium tests of a single `Locus`
+pub struct HardyWeinbergRecord {
+    locus: String,
+    group: Option<String>,
+    individuals: usize,
+    genotypes: Vec<GenotypeCount>,
+    chi_square: f32,
+    degrees_of_freedom: usize,
+    chi_square_p_value: f32,
+    exact_p_value: f32,
+}
+
+impl HardyWeinbergRecord {
+    pub fn locus(&self) -> &str {
+        &self.locus
+    }
+
+    /// The `Group` tested, or `None` when the whole `Sample` was tested
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// The number of diploid individuals typed at this locus
+    pub fn individuals(&self) -> usize {
+        self.individuals
+    }
+
+    /// Every genotype possible from the alleles present at this locus
+    pub fn genotypes(&self) -> &[GenotypeCount] {
+        &self.genotypes
+    }
+
+    pub fn chi_square(&self) -> f32 {
+        self.chi_square
+    }
+
+    pub fn degrees_of_freedom(&self) -> usize {
+        self.degrees_of_freedom
+    }
+
+    pub fn chi_square_p_value(&self) -> f32 {
+        self.chi_square_p_value
+    }
+
+    /// The Monte Carlo estimate of the exact test p-value
+    pub fn exact_p_value(&self) -> f32 {
+        self.exact_p_value
+    }
+}
+
+/// The Hardy-Weinberg equilibrium tests of every `Locus`
+pub struct HardyWeinbergTable {
+    records: Vec<HardyWeinbergRecord>,
+}
+
+impl HardyWeinbergTable {
+    pub fn records(&self) -> &[HardyWeinbergRecord] {
+        &self.records
+    }
+
+    /// Writes one comma separated row per record, preceded by a header
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "locus",
+            "group",
+            "individuals",
+            "chi_square",
+            "degrees_of_freedom",
+            "chi_square_p_value",
+            "exact_p_value",
+        ])?;
+        for record in self.records.iter() {
+            wtr.write_record(&[
+                record.locus.clone(),
+                record.group.clone().unwrap_or_default(),
+                record.individuals.to_string(),
+                record.chi_square.to_string(),
+                record.degrees_of_freedom.to_string(),
+                record.chi_square_p_value.to_string(),
+                record.exact_p_value.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Tests every `Locus` for departure from Hardy-Weinberg equilibrium
+///
+/// Only individuals carrying exactly two alleles at a locus are counted.
+/// Besides the chi-square test, the exact test of Guo & Thompson is
+/// estimated by Monte Carlo: alleles are shuffled among individuals
+/// `replicates` times and the p-value is the proportion of replicates
+/// whose genotype configuration is no more probable than the one observed.
+pub struct HardyWeinbergTest {
+    per_group: bool,
+    replicates: usize,
+    seed: Option<u64>,
+}
+
+impl Default for HardyWeinbergTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HardyWeinbergTest {
+    /// Construct a new test of the whole sample with 1000 Monte Carlo replicates
+    pub fn new() -> Self {
+        Self {
+            per_group: false,
+            replicates: 1000,
+            seed: None,
+        }
+    }
+
+    /// Test each `Group` separately instead of the whole sample
+    pub fn per_group(&mut self, per_group: bool) -> &mut Self {
+        self.per_group = per_group;
+        self
+    }
+
+    pub fn replicates(&mut self, replicates: usize) -> &mut Self {
+        self.replicates = replicates;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+pub trait HardyWeinberg {
+    fn hardy_weinberg(&self, test: &HardyWeinbergTest) -> Result<HardyWeinbergTable, Box<dyn Error>>;
+}
+
+impl HardyWeinberg for Sample {
+    fn hardy_weinberg(&self, test: &HardyWeinbergTest) -> Result<HardyWeinbergTable, Box<dyn Error>> {
+        let mut rng = self.random.or_seed(test.seed).rng();
+        let groups: Vec<Option<&String>> = if test.per_group {
+            self.group_names().into_iter().map(Some).collect()
+        } else {
+            vec![None]
+        };
+
+        let mut records = vec![];
+        for group in groups {
+            let individuals: Vec<&Individual> = self
+                .individuals
+                .values()
+                .filter(|individual| group.is_none_or(|g| individual.in_group(g)))
+                .collect();
+            for (name, locus) in self.loci.iter() {
+                let variations: Vec<String> = locus.variations.lock().unwrap().keys().cloned().collect();
+                let genotypes = diploid_genotypes(&individuals, locus);
+                let mut record = test_locus(&genotypes, &variations, test.replicates, &mut rng);
+                record.locus = name.clone();
+                record.group = group.cloned();
+                records.push(record);
+            }
+        }
+        Ok(HardyWeinbergTable { records })
+    }
+}
+
+/// The pair of variation indices carried by every diploid individual at `locus`
+fn diploid_genotypes(individuals: &[&Individual], locus: &Arc<Locus>) -> Vec<(usize, usize)> {
+    individuals
+        .iter()
+        .filter_map(|individual| {
+            let alleles: Vec<usize> = individual
+                .locus_counts(locus)
+                .iter()
+                .enumerate()
+                .flat_map(|(variation, count)| std::iter::repeat_n(variation, *count as usize))
+                .collect();
+            match alleles.as_slice() {
+                [a, b] => Some((*a, *b)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The part of the log probability of a genotype configuration under HWE
+/// that varies once allele counts are fixed
+fn ln_configuration_probability(genotypes: &BTreeMap<(usize, usize), u32>) -> f64 {
+    genotypes
+        .iter()
+        .map(|((a, b), n)| {
+            let heterozygous = if a != b { *n as f64 * 2f64.ln() } else { 0.0 };
+            heterozygous - ln_factorial(*n as usize)
+        })
+        .sum()
+}
+
+fn count_genotypes(genotypes: &[(usize, usize)]) -> BTreeMap<(usize, usize), u32> {
+    let mut counts = BTreeMap::new();
+    for (a, b) in genotypes.iter() {
+        *counts.entry((*a.min(b), *a.max(b))).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn test_locus<R: rand::Rng>(
+    genotypes: &[(usize, usize)],
+    variations: &[String],
+    replicates: usize,
+    rng: &mut R,
+) -> HardyWeinbergRecord {
+    let n = genotypes.len();
+    let mut allele_counts = vec![0u32; variations.len()];
+    for (a, b) in genotypes.iter() {
+        allele_counts[*a] += 1;
+        allele_counts[*b] += 1;
+    }
+    let present: Vec<usize> = (0..variations.len()).filter(|i| allele_counts[*i] > 0).collect();
+    let observed = count_genotypes(genotypes);
+
+    let mut counts = vec![];
+    let mut chi_square = 0.0;
+    for (i, a) in present.iter().enumerate() {
+        for b in present[i..].iter() {
+            let p = allele_counts[*a] as f64 / (2 * n) as f64;
+            let q = allele_counts[*b] as f64 / (2 * n) as f64;
+            let expected = if a == b { n as f64 * p * p } else { 2.0 * n as f64 * p * q };
+            let count = observed.get(&(*a, *b)).copied().unwrap_or(0);
+            chi_square += (count as f64 - expected).powf(2.0) / expected;
+            counts.push(GenotypeCount {
+                alleles: (variations[*a].clone(), variations[*b].clone()),
+                observed: count,
+                expected: expected as f32,
+            });
+        }
+    }
+
+    let k = present.len();
+    let degrees_of_freedom = if k > 1 { k * (k - 1) / 2 } else { 0 };
+
+    let exact_p_value = if degrees_of_freedom == 0 || replicates == 0 {
+        1.0
+    } else {
+        let threshold = ln_configuration_probability(&observed) + 1e-9;
+        let mut pool: Vec<usize> = genotypes.iter().flat_map(|(a, b)| vec![*a, *b]).collect();
+        let as_extreme = (0..replicates)
+            .filter(|_| {
+                pool.shuffle(rng);
+                let shuffled: Vec<(usize, usize)> =
+                    pool.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+                ln_configuration_probability(&count_genotypes(&shuffled)) <= threshold
+            })
+            .count();
+        as_extreme as f64 / replicates as f64
+    };
+
+    HardyWeinbergRecord {
+        locus: String::new(),
+        group: None,
+        individuals: n,
+        genotypes: counts,
+        chi_square: chi_square as f32,
+        degrees_of_freedom,
+        chi_square_p_value: chi_square_survival(chi_square, degrees_of_freedom) as f32,
+        exact_p_value: exact_p_value as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_hardy_weinberg_detects_heterozygote_deficit() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        let rows = "a,b\n".to_owned()
+            + &"1/1,1/2\n2/2,1/2\n".repeat(20)
+            + &"1/2,1/1\n2/2,2/2\n".repeat(5);
+        sample.observe(CsvBuilder::new().from_reader(Box::new(std::io::Cursor::new(rows)))?)?;
+        let table = sample.hardy_weinberg(HardyWeinbergTest::new().replicates(200).seed(3))?;
+
+        let a = &table.records()[0];
+        assert_eq!(a.locus(), "a");
+        assert_eq!(a.individuals(), 50);
+        assert_eq!(a.genotypes().len(), 3);
+        assert_eq!(a.degrees_of_freedom(), 1);
+        assert!(a.chi_square_p_value() < 0.001);
+        assert!(a.exact_p_value() < 0.01);
+
+        let mut csv = vec![];
+        table.write_csv(&mut csv)?;
+        assert_eq!(String::from_utf8(csv)?.lines().count(), 3);
+        Ok(())
+    }
+}
@@ -1,14 +1,171 @@
 use crate::prelude::*;
-use ndarray;
-use std::collections::HashMap;
+use crate::AlleleMatrix;
+use rand::seq::SliceRandom;
+use rand::Rng;
 use std::error::Error;
 
+/// The result of an index of association calculation
+///
+/// Besides the raw index of association, `I_A`, this carries the
+/// standardized index of association, `rbarD` (Agapow & Burt 2001),
+/// which does not scale with the number of loci and so can be compared
+/// between datasets typed on different marker panels.
 pub struct IndexOfAssociationSummary {
     index_of_association: f32,
+    rbar_d: f32,
+    variance: f32,
+    expected_variance: f32,
+    null: Option<NullDistribution>,
+}
+
+impl IndexOfAssociationSummary {
+    /// The index of association, `I_A`
+    pub fn index_of_association(&self) -> f32 {
+        self.index_of_association
+    }
+
+    /// The standardized index of association, `rbarD`
+    pub fn rbar_d(&self) -> f32 {
+        self.rbar_d
+    }
+
+    /// The observed variance of the distances summed over all loci, `V_O`
+    pub fn variance(&self) -> f32 {
+        self.variance
+    }
+
+    /// The variance expected under no linkage, `V_E`
+    ///
+    /// This is the sum of the variances of the distances at each locus.
+    pub fn expected_variance(&self) -> f32 {
+        self.expected_variance
+    }
+
+    /// The permuted statistics, if this summary came from a `PermutationTest`
+    pub fn null_distribution(&self) -> Option<&NullDistribution> {
+        self.null.as_ref()
+    }
+
+    /// The one sided p-value of `I_A`, if this summary came from a `PermutationTest`
+    pub fn p_value(&self) -> Option<f32> {
+        self.null
+            .as_ref()
+            .map(|null| null.p_value(self.index_of_association, &null.index_of_association))
+    }
+
+    /// The one sided p-value of `rbarD`, if this summary came from a `PermutationTest`
+    pub fn rbar_d_p_value(&self) -> Option<f32> {
+        self.null.as_ref().map(|null| null.p_value(self.rbar_d, &null.rbar_d))
+    }
+}
+
+/// The values of `I_A` and `rbarD` over all replicates of a `PermutationTest`
+pub struct NullDistribution {
+    index_of_association: Vec<f32>,
+    rbar_d: Vec<f32>,
+    failed: usize,
+}
+
+impl NullDistribution {
+    /// `I_A` of every permuted replicate
+    pub fn index_of_association(&self) -> &[f32] {
+        &self.index_of_association
+    }
+
+    /// `rbarD` of every permuted replicate
+    pub fn rbar_d(&self) -> &[f32] {
+        &self.rbar_d
+    }
+
+    /// The number of replicates left out because their statistics could
+    /// not be computed, such as when shuffling left fewer than two loci
+    /// whose distances vary
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    fn p_value(&self, observed: f32, null: &[f32]) -> f32 {
+        let extreme = null.iter().filter(|x| **x >= observed).count();
+        (extreme + 1) as f32 / (null.len() + 1) as f32
+    }
+}
+
+/// How alleles are shuffled among individuals to break linkage between loci
+pub enum Shuffle {
+    /// Shuffles whole single locus genotypes among individuals, independently
+    /// at each locus.
+    ///
+    /// Every genotype observed at a locus is kept intact, so heterozygosity and
+    /// the per-locus variances are the same in every replicate. Missing
+    /// genotypes stay with their individuals.
+    Genotypes,
+
+    /// Pools the alleles at each locus and deals them back out among individuals,
+    /// independently at each locus.
+    ///
+    /// Each individual keeps the number of alleles it had at a locus but
+    /// genotypes are rebuilt at random.
+    Alleles,
+}
+
+/// Tests the index of association against a permuted null distribution
+///
+/// Each replicate shuffles the data according to `shuffle` and recomputes
+/// `I_A` and `rbarD`, among the individuals the observed statistics were
+/// computed from. Giving a `seed` makes the test reproducible.
+pub struct PermutationTest {
+    replicates: usize,
+    shuffle: Shuffle,
+    seed: Option<u64>,
+}
+
+impl Default for PermutationTest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PermutationTest {
+    /// Construct a new test with 999 replicates shuffling genotypes
+    pub fn new() -> Self {
+        Self {
+            replicates: 999,
+            shuffle: Shuffle::Genotypes,
+            seed: None,
+        }
+    }
+
+    pub fn replicates(&mut self, replicates: usize) -> &mut Self {
+        self.replicates = replicates;
+        self
+    }
+
+    pub fn shuffle(&mut self, shuffle: Shuffle) -> &mut Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
 }
 
 pub trait IndexOfAssociation {
+    /// Computes `I_A` and `rbarD` over all loci
+    ///
+    /// As in poppr, loci whose distances between individuals do not vary,
+    /// such as monomorphic loci, are left out. It is an error when fewer
+    /// than two loci remain. Individuals with a missing genotype at any
+    /// other locus are left out too, unless the `Sample` was imputed, so
+    /// that every pair of individuals is compared over the same loci.
     fn index_of_association(&mut self) -> Result<IndexOfAssociationSummary, Box<dyn Error>>;
+
+    /// Computes the index of association along with its null distribution
+    fn index_of_association_test(
+        &mut self,
+        test: &PermutationTest,
+    ) -> Result<IndexOfAssociationSummary, Box<dyn Error>>;
 }
 
 impl IndexOfAssociation for Sample {
@@ -16,70 +173,328 @@ impl IndexOfAssociation for Sample {
         if self.matrix.dirty {
             self.flush()?;
         }
+        self.matrix.index_of_association()
+    }
 
-        let freqs = self.matrix.frequency()?;
-        let n_freqs = freqs.shape()[0];
+    fn index_of_association_test(
+        &mut self,
+        test: &PermutationTest,
+    ) -> Result<IndexOfAssociationSummary, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let random = self.random.or_seed(test.seed);
+        self.matrix.permutation_test(test, random)
+    }
+}
 
-        let mut indices: HashMap<(usize, usize), usize> = HashMap::new();
-        let mut counter = 0;
-        for i in 0..n_freqs - 1 {
-            for j in i..n_freqs {
-                indices.insert((i, j), counter);
-                counter += 1;
-            }
+impl IndexOfAssociation for AlleleMatrix {
+    fn index_of_association(&mut self) -> Result<IndexOfAssociationSummary, Box<dyn Error>> {
+        let (summary, _) = self.association()?.ok_or(TOO_FEW_LOCI)?;
+        Ok(summary)
+    }
+
+    fn index_of_association_test(
+        &mut self,
+        test: &PermutationTest,
+    ) -> Result<IndexOfAssociationSummary, Box<dyn Error>> {
+        self.permutation_test(test, test.seed.into())
+    }
+}
+
+/// The statistics along with the rows they were computed from
+type Association = (IndexOfAssociationSummary, Vec<usize>);
+
+/// The error of data without two loci whose distances vary
+const TOO_FEW_LOCI: &str =
+    "the index of association needs at least two polymorphic loci typed in at least two individuals";
+
+impl AlleleMatrix {
+    /// Computes the index of association, or `None` when fewer than two
+    /// loci have distances that vary between the individuals typed at
+    /// every locus
+    pub(crate) fn association(&self) -> Result<Option<Association>, Box<dyn Error>> {
+        let freqs = self.frequency()?;
+        let polymorphic: Vec<&(usize, usize)> = self
+            .loci
+            .iter()
+            .filter(|(start, end)| {
+                (*start..*end)
+                    .filter(|column| self.data.column(*column).sum() > 0)
+                    .count()
+                    > 1
+            })
+            .collect();
+        // Distances are only summed over loci typed in both individuals of
+        // every pair when all individuals are typed at every locus.
+        let rows: Vec<usize> = (0..freqs.shape()[0])
+            .filter(|row| polymorphic.iter().all(|(start, _)| !freqs[[*row, *start]].is_nan()))
+            .collect();
+        let n_freqs = rows.len();
+        if n_freqs < 2 {
+            return Ok(None);
         }
 
         let n_distances = n_freqs * (n_freqs - 1) / 2;
-        let n_loci = self.matrix.loci.len();
-        let mut distances = ndarray::Array::zeros((n_distances, n_loci));
-
-        (0..n_freqs - 1).for_each(|i| {
-            ((i + 1)..n_freqs).for_each(|j| {
-                self.matrix
-                    .loci
-                    .iter()
-                    .enumerate()
-                    .for_each(|(idx, (start, end))| {
-                        distances[[indices[&(i, j)], idx]] =
-                            (&freqs.row(i).slice(ndarray::s![*start..*end])
-                                - &freqs.row(j).slice(ndarray::s![*start..*end]))
-                                .map(|x| x.abs())
-                                .sum();
-                    });
+        let mut distances = ndarray::Array2::<f32>::zeros((n_distances, polymorphic.len()));
+
+        let mut pair = 0;
+        for (a, i) in rows.iter().enumerate() {
+            for j in rows[a + 1..].iter() {
+                for (idx, (start, end)) in polymorphic.iter().enumerate() {
+                    distances[[pair, idx]] = (&freqs.row(*i).slice(ndarray::s![*start..*end])
+                        - &freqs.row(*j).slice(ndarray::s![*start..*end]))
+                        .map(|x| x.abs())
+                        .sum();
+                }
+                pair += 1;
+            }
+        }
+
+        // Loci whose distances do not vary carry no information on linkage.
+        let informative: Vec<usize> = (0..polymorphic.len())
+            .filter(|n| {
+                let column = distances.column(*n);
+                let max = column.fold(f32::MIN, |max, x| max.max(*x));
+                let min = column.fold(f32::MAX, |min, x| min.min(*x));
+                max - min > 1e-6
             })
-        });
-
-        let variance = (ndarray::Zip::from(distances.genrows())
-            .apply_collect(|row| row.sum().powf(2.0))
-            .sum()
-            - ndarray::Zip::from(distances.genrows())
-                .apply_collect(|row| row.sum())
-                .sum()
-                .powf(2.0)
-                / n_distances as f32)
-            / n_distances as f32;
-
-        let expected_variance: f32 = (0..n_loci)
+            .collect();
+        if informative.len() < 2 {
+            return Ok(None);
+        }
+        let distances = distances.select(ndarray::Axis(1), &informative);
+        let n_loci = informative.len();
+
+        // Variance of a set of distances using the raw sums, as in Agapow & Burt.
+        let variance_of = |sum: f32, sum_of_squares: f32| {
+            (sum_of_squares - sum.powf(2.0) / n_distances as f32) / n_distances as f32
+        };
+
+        let totals = distances.sum_axis(ndarray::Axis(1));
+        let variance = variance_of(totals.sum(), totals.map(|x| x.powf(2.0)).sum());
+
+        let locus_variances: Vec<f32> = (0..n_loci)
             .map(|n| {
-                (distances.column(n).map(|x| x.powf(2.0)).sum()
-                    - (distances.column(n).sum() / n_distances as f32))
-                    / n_distances as f32
+                let column = distances.column(n);
+                variance_of(column.sum(), column.map(|x| x.powf(2.0)).sum())
             })
-            .sum();
+            .collect();
+        let expected_variance: f32 = locus_variances.iter().sum();
+
+        let mut covariance_bound = 0.0;
+        for j in 0..n_loci {
+            for k in (j + 1)..n_loci {
+                covariance_bound += (locus_variances[j] * locus_variances[k]).sqrt();
+            }
+        }
+
+        let summary = IndexOfAssociationSummary {
+            index_of_association: (variance / expected_variance) - 1.0,
+            rbar_d: (variance - expected_variance) / (2.0 * covariance_bound),
+            variance,
+            expected_variance,
+            null: None,
+        };
+        Ok(Some((summary, rows)))
+    }
 
-        let index_of_association =  (variance / expected_variance) - 1.0;
+    /// Computes the index of association and its null distribution,
+    /// shuffling with generators drawn from `random`
+    fn permutation_test(
+        &mut self,
+        test: &PermutationTest,
+        random: RandomSource,
+    ) -> Result<IndexOfAssociationSummary, Box<dyn Error>> {
+        let (mut summary, rows) = self.association()?.ok_or(TOO_FEW_LOCI)?;
+        let observed = self.select_rows(&rows);
+        let mut rng = random.rng();
+        let mut null = NullDistribution {
+            index_of_association: Vec::with_capacity(test.replicates),
+            rbar_d: Vec::with_capacity(test.replicates),
+            failed: 0,
+        };
+        for _ in 0..test.replicates {
+            match observed.shuffled(&test.shuffle, &mut rng).association()? {
+                Some((replicate, _)) => {
+                    null.index_of_association.push(replicate.index_of_association);
+                    null.rbar_d.push(replicate.rbar_d);
+                }
+                None => null.failed += 1,
+            }
+        }
+        summary.null = Some(null);
+        Ok(summary)
+    }
+}
 
-        Ok(IndexOfAssociationSummary {
-            index_of_association: index_of_association,
-        })
+impl AlleleMatrix {
+    /// Returns a copy of this matrix with each locus shuffled independently
+    /// among the individuals typed there
+    fn shuffled<R: Rng>(&self, shuffle: &Shuffle, rng: &mut R) -> AlleleMatrix {
+        let mut data = self.data.clone();
+        let n_individuals = data.shape()[0];
+        for (start, end) in self.loci.iter() {
+            let block = self.data.slice(ndarray::s![.., *start..*end]);
+            match shuffle {
+                Shuffle::Genotypes => {
+                    let typed: Vec<usize> = (0..n_individuals)
+                        .filter(|row| block.row(*row).sum() > 0)
+                        .collect();
+                    let mut order = typed.clone();
+                    order.shuffle(rng);
+                    for (row, from) in typed.into_iter().zip(order) {
+                        data.slice_mut(ndarray::s![row, *start..*end])
+                            .assign(&block.row(from));
+                    }
+                }
+                Shuffle::Alleles => {
+                    let mut pool: Vec<usize> = vec![];
+                    for row in block.genrows() {
+                        for (allele, count) in row.iter().enumerate() {
+                            pool.extend(std::iter::repeat_n(allele, *count as usize));
+                        }
+                    }
+                    pool.shuffle(rng);
+                    let mut dealt = pool.into_iter();
+                    for (row, counts) in block.genrows().into_iter().enumerate() {
+                        let mut target = data.slice_mut(ndarray::s![row, *start..*end]);
+                        target.fill(0);
+                        for allele in dealt.by_ref().take(counts.sum() as usize) {
+                            target[allele] += 1;
+                        }
+                    }
+                }
+            }
+        }
+        self.with_data(data)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_index_of_association_of_identical_loci_is_one() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,b\n1/1,1/1\n1/2,1/2\n2/2,2/2\n1/3,1/3\n3/3,3/3".as_bytes(),
+        ))?)?;
+        let summary = sample.index_of_association()?;
+        assert!((summary.index_of_association() - 1.0).abs() < 1e-5);
+        assert!((summary.rbar_d() - 1.0).abs() < 1e-5);
+        assert!((summary.variance() - 2.0 * summary.expected_variance()).abs() < 1e-5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_monomorphic_loci_are_left_out() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,b,c\n1/1,1/1,4/4\n1/2,1/2,4/4\n2/2,2/2,4/4\n1/3,1/3,4/4".as_bytes(),
+        ))?)?;
+        let summary = sample.index_of_association()?;
+        assert!((summary.rbar_d() - 1.0).abs() < 1e-5);
+
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,c\n1/1,4/4\n1/2,4/4\n2/2,4/4".as_bytes(),
+        ))?)?;
+        assert!(sample.index_of_association().is_err());
+        Ok(())
+    }
 
-    //#[test]
-    //fn test_index_of_association() -> Result<(), Box<dyn Error>> {
-        
-    //}
+    #[test]
+    fn test_missing_genotypes_are_left_out() -> Result<(), Box<dyn Error>> {
+        let data = "a,b,c\n1/1,1/1,1/2\n1/2,1/2,1/1\n2/2,2/2,2/2\n1/3,1/3,1/1\n3/3,3/3,2/2";
+        let mut complete = Sample::new();
+        complete.observe(CsvBuilder::new().from_reader(Box::new(data.as_bytes()))?)?;
+        let mut missing = Sample::new();
+        missing.observe(
+            CsvBuilder::new()
+                .missing_codes(["?"].iter().map(|s| s.to_string()).collect())
+                .from_reader(Box::new(std::io::Cursor::new(format!("{}\n?,2/3,1/2", data))))?,
+        )?;
+        let expected = complete.index_of_association()?;
+        let summary = missing.index_of_association()?;
+        assert!(!summary.rbar_d().is_nan());
+        assert_eq!(summary.index_of_association(), expected.index_of_association());
+        assert_eq!(summary.rbar_d(), expected.rbar_d());
+
+        // Replicates shuffle the complete individuals only, so none fails.
+        let mut test = PermutationTest::new();
+        test.replicates(20).seed(2);
+        let tested = missing.index_of_association_test(&test)?;
+        let null = tested.null_distribution().unwrap();
+        assert_eq!((null.rbar_d().len(), null.failed()), (20, 0));
+        let matrix = &missing.matrix;
+        let shuffled = matrix.shuffled(&Shuffle::Genotypes, &mut RandomSource::seeded(2).rng());
+        let untyped = |m: &AlleleMatrix| m.data().sum_axis(ndarray::Axis(1)).mapv(|n| n < 6);
+        assert_eq!(untyped(&shuffled), untyped(matrix));
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_of_association_test_is_reproducible() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,b,c\n1/1,1/1,2/2\n1/2,1/2,1/2\n2/2,2/2,1/1\n1/3,1/3,3/3\n3/3,3/3,1/3".as_bytes(),
+        ))?)?;
+        let first = sample.index_of_association_test(PermutationTest::new().replicates(50).seed(7))?;
+        let second = sample.index_of_association_test(
+            PermutationTest::new().replicates(50).shuffle(Shuffle::Genotypes).seed(7),
+        )?;
+        let null = first.null_distribution().unwrap();
+        assert_eq!(null.index_of_association().len(), 50);
+        assert_eq!(null.rbar_d(), second.null_distribution().unwrap().rbar_d());
+        let p = first.p_value().unwrap();
+        assert!(p > 0.0 && p <= 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shuffles_separate_linked_from_unlinked_loci() -> Result<(), Box<dyn Error>> {
+        let genotypes = ["1/1", "1/2", "2/2", "1/3", "2/3", "3/3"];
+        let mut linked = String::from("a,b,c");
+        let mut unlinked = String::from("a,b,c");
+        for (i, x) in genotypes.iter().enumerate() {
+            for (j, y) in genotypes.iter().enumerate() {
+                let z = genotypes[(i + 2 * j) % genotypes.len()];
+                linked.push_str(&format!("\n{},{},{}", x, x, x));
+                unlinked.push_str(&format!("\n{},{},{}", x, y, z));
+            }
+        }
+        let read = |data: String| -> Result<Sample, Box<dyn Error>> {
+            let mut sample = Sample::new();
+            sample.observe(CsvBuilder::new().from_reader(Box::new(std::io::Cursor::new(data)))?)?;
+            Ok(sample)
+        };
+        let mut linked = read(linked)?;
+        let mut unlinked = read(unlinked)?;
+
+        for shuffle in [Shuffle::Genotypes, Shuffle::Alleles] {
+            let mut test = PermutationTest::new();
+            test.replicates(99).shuffle(shuffle).seed(3);
+            let summary = linked.index_of_association_test(&test)?;
+            assert!(summary.p_value().unwrap() <= 0.01);
+            assert!(summary.rbar_d_p_value().unwrap() <= 0.01);
+            let summary = unlinked.index_of_association_test(&test)?;
+            assert!(summary.p_value().unwrap() > 0.1);
+        }
+
+        // Dealing alleles keeps the alleles of every locus and how many
+        // each individual carries.
+        let matrix = &linked.matrix;
+        let shuffled = matrix.shuffled(&Shuffle::Alleles, &mut RandomSource::seeded(5).rng());
+        assert_ne!(shuffled.data(), matrix.data());
+        for (start, end) in matrix.loci() {
+            let block = |m: &AlleleMatrix| m.data().slice(ndarray::s![.., *start..*end]).to_owned();
+            let (original, dealt) = (block(matrix), block(&shuffled));
+            assert_eq!(original.sum_axis(ndarray::Axis(0)), dealt.sum_axis(ndarray::Axis(0)));
+            assert_eq!(original.sum_axis(ndarray::Axis(1)), dealt.sum_axis(ndarray::Axis(1)));
+        }
+        Ok(())
+    }
 }
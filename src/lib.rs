@@ -1,15 +1,36 @@
 #![crate_name = "genomics"]
-use ndarray;
 use ndarray::ShapeBuilder;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex};
 
+use missing_data::Imputation;
+use random::RandomSource;
+
 pub mod prelude;
 
 pub mod observable;
 pub mod index_of_association;
+pub mod multilocus_genotype;
+pub mod hardy_weinberg;
+pub mod diversity;
+pub mod f_statistics;
+pub mod differentiation;
+pub mod distance;
+pub mod population_distance;
+pub mod missing_data;
+pub mod pca;
+pub mod dapc;
+pub mod amova;
+pub mod linkage_disequilibrium;
+pub mod sliding_window;
+pub mod rst;
+pub mod resampling;
+pub mod random;
+
+mod distributions;
+mod linalg;
 
 pub type Groups = HashMap<String, Arc<Group>>;
 pub type Meta = HashMap<String, String>;
@@ -43,15 +64,133 @@ impl Variation {
     }
 }
 
+/// The kind of marker a `Locus` holds, which decides how its alleles are
+/// compared
+///
+/// Only the `Variation` names of microsatellites are read as allele sizes,
+/// so stepwise mutation aware statistics such as `Bruvo` distance treat
+/// every other kind of locus as identical or different alleles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum LocusHint {
+    /// Alleles are distinct states without an order
     Classical,
+    /// Alleles are named by their size, in base pairs or repeat units
+    #[default]
     Microsatellite,
+    /// Alleles are single nucleotides
+    Snp,
+}
+
+impl LocusHint {
+    /// The size of an allele named `variation` at a locus of this kind,
+    /// which only microsatellites have
+    pub fn allele_size(&self, variation: &str) -> Option<f32> {
+        match self {
+            LocusHint::Microsatellite => variation.trim().parse().ok(),
+            LocusHint::Classical | LocusHint::Snp => None,
+        }
+    }
+}
+
+impl std::str::FromStr for LocusHint {
+    type Err = Box<dyn Error>;
+
+    /// Parses `classical`, `microsatellite` or `snp`, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "classical" => Ok(Self::Classical),
+            "microsatellite" => Ok(Self::Microsatellite),
+            "snp" => Ok(Self::Snp),
+            _ => Err(format!("unknown locus hint `{}`", s).into()),
+        }
+    }
+}
+
+/// A single fact about where a `Locus` lies or what it repeats
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Annotation {
+    Chromosome(String),
+    /// The position on the chromosome, in base pairs
+    Position(u64),
+    /// The reference allele
+    Reference(String),
+    /// The length of the microsatellite repeat unit, in base pairs
+    RepeatLength(u32),
+    /// The microsatellite repeat unit, such as `CA`
+    Motif(String),
+    /// The kind of marker
+    Hint(LocusHint),
+    /// A site level field, such as a VCF `INFO` entry, by key and value
+    Info(String, String),
+}
+
+/// The genomic coordinates and repeat structure known of a `Locus`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LocusAnnotation {
+    chromosome: Option<String>,
+    position: Option<u64>,
+    reference: Option<String>,
+    repeat_length: Option<u32>,
+    motif: Option<String>,
+    hint: LocusHint,
+    info: BTreeMap<String, String>,
+}
+
+impl LocusAnnotation {
+    pub fn chromosome(&self) -> Option<&str> {
+        self.chromosome.as_deref()
+    }
+
+    /// The position on the chromosome, in base pairs
+    pub fn position(&self) -> Option<u64> {
+        self.position
+    }
+
+    /// The reference allele
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    /// The length of the repeat unit, or else of the motif, in base pairs
+    pub fn repeat_length(&self) -> Option<u32> {
+        self.repeat_length
+            .or_else(|| self.motif.as_ref().map(|motif| motif.len() as u32))
+    }
+
+    /// The repeat unit
+    pub fn motif(&self) -> Option<&str> {
+        self.motif.as_deref()
+    }
+
+    /// The kind of marker, `Microsatellite` unless annotated
+    pub fn hint(&self) -> LocusHint {
+        self.hint
+    }
+
+    /// The value of a site level field, such as a VCF `INFO` entry
+    pub fn info(&self, key: &str) -> Option<&str> {
+        self.info.get(key).map(|value| value.as_str())
+    }
+
+    fn set(&mut self, annotation: Annotation) {
+        match annotation {
+            Annotation::Chromosome(chromosome) => self.chromosome = Some(chromosome),
+            Annotation::Position(position) => self.position = Some(position),
+            Annotation::Reference(reference) => self.reference = Some(reference),
+            Annotation::RepeatLength(length) => self.repeat_length = Some(length),
+            Annotation::Motif(motif) => self.motif = Some(motif),
+            Annotation::Hint(hint) => self.hint = hint,
+            Annotation::Info(key, value) => {
+                self.info.insert(key, value);
+            }
+        }
+    }
 }
 
 pub struct Locus {
     name: String,
     variations: Variations,
-    hint: LocusHint,
+    annotation: Mutex<LocusAnnotation>,
 }
 
 impl Hash for Locus {
@@ -73,9 +212,29 @@ impl Locus {
         Self {
             name: name.into(),
             variations: Variations::new(Mutex::new(BTreeMap::new())),
-            hint: LocusHint::Microsatellite,
+            annotation: Mutex::new(LocusAnnotation::default()),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// What is known of this `Locus`'s coordinates and repeat structure
+    pub fn annotation(&self) -> LocusAnnotation {
+        self.annotation.lock().unwrap().clone()
+    }
+
+    /// The kind of marker this `Locus` holds
+    pub fn hint(&self) -> LocusHint {
+        self.annotation.lock().unwrap().hint
+    }
+
+    /// The size of an allele, read from its `Variation` name at
+    /// microsatellite loci and `None` at every other kind of locus
+    pub fn allele_size(&self, variation: &str) -> Option<f32> {
+        self.hint().allele_size(variation)
+    }
 }
 
 #[derive(Hash, PartialEq, Eq)]
@@ -89,11 +248,58 @@ impl Group {
     }
 }
 
+/// The alleles of one `Individual` at one `Locus`
+///
+/// Alleles keep the order in which they were observed, so phased data
+/// can be told apart, while `unordered()` and `counts()` compare
+/// genotypes as multisets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Genotype {
+    alleles: Vec<String>,
+}
+
+impl Genotype {
+    /// The names of the alleles in the order they were observed
+    pub fn ordered(&self) -> &[String] {
+        &self.alleles
+    }
+
+    /// The names of the alleles sorted by name
+    pub fn unordered(&self) -> Vec<&str> {
+        let mut alleles: Vec<&str> = self.alleles.iter().map(|a| a.as_str()).collect();
+        alleles.sort_unstable();
+        alleles
+    }
+
+    /// The number of copies of each variation
+    pub fn counts(&self) -> BTreeMap<&str, AlleleCount> {
+        let mut counts = BTreeMap::new();
+        for allele in self.alleles.iter() {
+            *counts.entry(allele.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// The number of alleles observed
+    pub fn ploidy(&self) -> usize {
+        self.alleles.len()
+    }
+
+    /// Whether every allele is the same variation
+    pub fn is_homozygous(&self) -> bool {
+        self.alleles.windows(2).all(|pair| pair[0] == pair[1])
+    }
+}
+
+#[derive(Clone)]
 pub struct Individual {
     name: String,
     genome: Genome,
+    alleles: HashMap<String, Vec<Arc<Variation>>>,
+    missing: HashSet<String>,
     groups: HashSet<Arc<Group>>,
     meta: Meta,
+    ploidy: Option<usize>,
 }
 
 impl Individual {
@@ -101,41 +307,145 @@ impl Individual {
         Self {
             name: name.into(),
             genome: Genome::new(),
+            alleles: HashMap::new(),
+            missing: HashSet::new(),
             groups: HashSet::new(),
             meta: Meta::new(),
+            ploidy: None,
         }
     }
+
+    /// The name this `Individual` was observed under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The ploidy set for this `Individual`, if any
+    pub fn ploidy(&self) -> Option<usize> {
+        self.ploidy
+    }
+
+    /// The genotype of this `Individual` at the named `Locus`
+    ///
+    /// Returns `None` when the genotype is missing.
+    pub fn genotype(&self, locus: &str) -> Option<Genotype> {
+        if self.missing.contains(locus) {
+            return None;
+        }
+        self.alleles.get(locus).map(|alleles| Genotype {
+            alleles: alleles.iter().map(|variation| variation.name.clone()).collect(),
+        })
+    }
+
+    /// Whether the genotype at the named `Locus` is missing
+    ///
+    /// A genotype is missing when it was observed as missing, even if
+    /// some of its alleles were observed, or when no allele was observed.
+    pub fn is_missing(&self, locus: &str) -> bool {
+        self.missing.contains(locus) || !self.alleles.contains_key(locus)
+    }
+
+    /// Whether this `Individual` belongs to the named `Group`
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g.name == group)
+    }
+
+    /// The count of every variation of `locus` in this `Individual`
+    ///
+    /// Counts are in the same order as the `Locus`'s variations, and all
+    /// zero when the genotype is missing.
+    pub(crate) fn locus_counts(&self, locus: &Arc<Locus>) -> Vec<AlleleCount> {
+        let missing = self.missing.contains(&locus.name);
+        locus
+            .variations
+            .lock()
+            .unwrap()
+            .values()
+            .map(|variation| match missing {
+                true => 0,
+                false => self
+                    .genome
+                    .get(&(locus.clone(), variation.clone()))
+                    .copied()
+                    .unwrap_or(0),
+            })
+            .collect()
+    }
 }
 
+/// Allele counts with one row per `Individual` and one column per `Variation`
+///
+/// The columns of each `Locus` are contiguous; `loci()` holds the
+/// `(start, end)` column range of every locus. Missing genotypes have
+/// no alleles.
 pub struct AlleleMatrix {
     data: ndarray::Array2<AlleleCount>,
     loci: Vec<(usize, usize)>,
+    individual_names: Vec<String>,
+    variation_names: Vec<String>,
+    repeat_lengths: Vec<Option<u32>>,
+    allele_sizes: Vec<Option<f32>>,
+    imputed: Option<ndarray::Array1<f32>>,
     dirty: bool,
 }
 
+impl Default for AlleleMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AlleleMatrix {
     pub fn new() -> Self {
         Self {
             data: ndarray::Array2::<AlleleCount>::zeros((0, 0)),
             loci: vec![],
+            individual_names: vec![],
+            variation_names: vec![],
+            repeat_lengths: vec![],
+            allele_sizes: vec![],
+            imputed: None,
             dirty: false,
         }
     }
 
     pub fn from_vec(individuals: usize, loci: Vec<(usize, usize)>, data: Vec<AlleleCount>) -> Result<Self, Box<dyn Error>> {
-        let alleles = data.len() / individuals;
-        Ok(Self {
-            data: ndarray::Array::from_shape_vec((individuals, alleles).strides((alleles, 1)), data)?,
-            loci: loci,
+        // Without individuals the columns are only known from the loci.
+        let alleles = data
+            .len()
+            .checked_div(individuals)
+            .unwrap_or_else(|| loci.last().map_or(0, |(_, end)| *end));
+        let mut matrix = Self::new();
+        matrix.data = ndarray::Array::from_shape_vec((individuals, alleles).strides((alleles, 1)), data)?;
+        matrix.loci = loci;
+        Ok(matrix)
+    }
+
+    /// A matrix of other counts for the same rows and columns
+    ///
+    /// Every matrix derived from another starts from here, so that each
+    /// field is carried over in one place.
+    pub(crate) fn with_data(&self, data: ndarray::Array2<AlleleCount>) -> AlleleMatrix {
+        AlleleMatrix {
+            data,
+            loci: self.loci.clone(),
+            individual_names: self.individual_names.clone(),
+            variation_names: self.variation_names.clone(),
+            repeat_lengths: self.repeat_lengths.clone(),
+            allele_sizes: self.allele_sizes.clone(),
+            imputed: self.imputed.clone(),
             dirty: false,
-        })
+        }
     }
 
     /// Computes the frequency matrix from allele counts
+    ///
+    /// Missing genotypes are `NaN`, or the imputed frequencies when the
+    /// `Sample` was mean imputed.
     pub fn frequency(&self) -> Result<ndarray::Array2<f32>, Box<dyn Error>> {
         let mut freqs = ndarray::Array2::from_elem(self.data.dim(), 0.0);
-        let loci: [(usize, usize); 2] = [(0,3), (3, 6)];
-    
+        let loci = &self.loci;
+
         ndarray::Zip::from(freqs.genrows_mut())
         .and(self.data.genrows())
         .apply(|mut freqs, row| {
@@ -145,8 +455,172 @@ impl AlleleMatrix {
             }).collect::<Vec<_>>());
             freqs.assign(&x);
         });
+        if let Some(imputed) = &self.imputed {
+            for mut row in freqs.genrows_mut() {
+                for (start, end) in loci.iter().filter(|(start, end)| start < end) {
+                    if row[*start].is_nan() {
+                        row.slice_mut(ndarray::s![*start..*end])
+                            .assign(&imputed.slice(ndarray::s![*start..*end]));
+                    }
+                }
+            }
+        }
         Ok(freqs)
     }
+
+    /// Computes the allele frequencies of a population from its rows
+    ///
+    /// Each locus is the mean of the individual frequencies at that
+    /// locus, skipping individuals that carry no alleles there, so
+    /// imputed genotypes never contribute.
+    pub fn population_frequency(&self, rows: &[usize]) -> Result<ndarray::Array1<f32>, Box<dyn Error>> {
+        let freqs = self.frequency()?;
+        let mut population = ndarray::Array1::<f32>::zeros(freqs.shape()[1]);
+        for (start, end) in self.loci.iter().filter(|(start, end)| start < end) {
+            let typed: Vec<usize> = rows
+                .iter()
+                .copied()
+                .filter(|row| self.data.slice(ndarray::s![*row, *start..*end]).sum() > 0)
+                .collect();
+            for column in *start..*end {
+                population[column] = typed.iter().map(|row| freqs[[*row, column]]).sum::<f32>()
+                    / typed.len() as f32;
+            }
+        }
+        Ok(population)
+    }
+
+    /// The number of individuals, or rows, in this matrix
+    pub fn individuals(&self) -> usize {
+        self.data.shape()[0]
+    }
+
+    /// The allele counts
+    pub fn data(&self) -> &ndarray::Array2<AlleleCount> {
+        &self.data
+    }
+
+    /// The `(start, end)` column range of every locus
+    pub fn loci(&self) -> &[(usize, usize)] {
+        &self.loci
+    }
+
+    /// The name of the `Individual` in each row
+    ///
+    /// Empty when the matrix was not built from a `Sample`.
+    pub fn individual_names(&self) -> &[String] {
+        &self.individual_names
+    }
+
+    /// The name of the `Variation` in each column
+    ///
+    /// Empty when the matrix was not built from a `Sample`.
+    pub fn variation_names(&self) -> &[String] {
+        &self.variation_names
+    }
+
+    /// The annotated repeat length of every locus
+    ///
+    /// Empty when the matrix was not built from a `Sample`.
+    pub fn repeat_lengths(&self) -> &[Option<u32>] {
+        &self.repeat_lengths
+    }
+
+    /// The size of the allele of every column, as given by
+    /// `Locus::allele_size()`
+    ///
+    /// Empty when the matrix was not built from a `Sample`.
+    pub fn allele_sizes(&self) -> &[Option<f32>] {
+        &self.allele_sizes
+    }
+
+    /// The allele frequencies of one row at one locus
+    ///
+    /// Returns `None` when the individual carries no alleles at the
+    /// locus, unless the `Sample` was mean imputed.
+    pub fn locus_frequency(&self, row: usize, locus: usize) -> Option<ndarray::Array1<f32>> {
+        let (start, end) = self.loci[locus];
+        let counts = self.data.slice(ndarray::s![row, start..end]);
+        let total = counts.sum();
+        if total == 0 {
+            let imputed = self.imputed.as_ref()?.slice(ndarray::s![start..end]).to_owned();
+            return if start < end && !imputed[0].is_nan() {
+                Some(imputed)
+            } else {
+                None
+            };
+        }
+        Some(counts.map(|count| *count as f32 / total as f32))
+    }
+
+    /// A matrix of the same individuals holding only the given loci, in order
+    pub(crate) fn select_loci(&self, loci: &[usize]) -> AlleleMatrix {
+        let columns: Vec<usize> = loci
+            .iter()
+            .flat_map(|locus| self.loci[*locus].0..self.loci[*locus].1)
+            .collect();
+        let mut start = 0;
+        let ranges = loci
+            .iter()
+            .map(|locus| {
+                let end = start + self.loci[*locus].1 - self.loci[*locus].0;
+                let range = (start, end);
+                start = end;
+                range
+            })
+            .collect();
+        let mut matrix = self.with_data(self.data.select(ndarray::Axis(1), &columns));
+        matrix.loci = ranges;
+        matrix.variation_names = columns
+            .iter()
+            .filter_map(|column| self.variation_names.get(*column).cloned())
+            .collect();
+        matrix.repeat_lengths = loci
+            .iter()
+            .filter_map(|locus| self.repeat_lengths.get(*locus).copied())
+            .collect();
+        matrix.allele_sizes = columns
+            .iter()
+            .filter_map(|column| self.allele_sizes.get(*column).copied())
+            .collect();
+        matrix.imputed = self.imputed.as_ref().map(|imputed| imputed.select(ndarray::Axis(0), &columns));
+        matrix
+    }
+
+    /// A matrix of the given rows, in order and possibly repeated
+    pub(crate) fn select_rows(&self, rows: &[usize]) -> AlleleMatrix {
+        let mut matrix = self.with_data(self.data.select(ndarray::Axis(0), rows));
+        matrix.individual_names = rows
+            .iter()
+            .filter_map(|row| self.individual_names.get(*row).cloned())
+            .collect();
+        matrix
+    }
+}
+
+/// A symmetric matrix of values between pairs of labelled items
+///
+/// Rows and columns are both in the order of `labels()`.
+pub struct PairwiseMatrix {
+    labels: Vec<String>,
+    data: ndarray::Array2<f32>,
+}
+
+impl PairwiseMatrix {
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn data(&self) -> &ndarray::Array2<f32> {
+        &self.data
+    }
+
+    /// The value between the items labelled `a` and `b`
+    pub fn get(&self, a: &str, b: &str) -> Option<f32> {
+        let i = self.labels.iter().position(|label| label == a)?;
+        let j = self.labels.iter().position(|label| label == b)?;
+        Some(self.data[[i, j]])
+    }
 }
 
 /// An observation of an Individual
@@ -162,6 +636,14 @@ pub enum Observation {
     /// An `Observation` that an `Individual` has associated metadata
     /// Individual's name, Meta data description, Meta data content. 
     Meta(String, String, String),
+
+    /// An `Observation` that an `Individual`'s genotype at a `Locus` is missing
+    /// Individual's name, Locus's name
+    Missing(String, String),
+
+    /// An `Observation` of where a `Locus` lies or what it repeats
+    /// Locus's name, Annotation
+    Annotation(String, Annotation),
 }
 
 pub struct Sample {
@@ -169,6 +651,16 @@ pub struct Sample {
     groups: Groups,
     individuals: Individuals,
     matrix: AlleleMatrix,
+    ploidy: Option<usize>,
+    locus_ploidy: HashMap<String, usize>,
+    imputation: Imputation,
+    random: RandomSource,
+}
+
+impl Default for Sample {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Sample {
@@ -182,6 +674,30 @@ impl Sample {
             groups: Groups::new(),
             individuals: Individuals::new(),
             matrix: AlleleMatrix::new(),
+            ploidy: None,
+            locus_ploidy: HashMap::new(),
+            imputation: Imputation::Ignore,
+            random: RandomSource::new(),
+        }
+    }
+
+    /// A `Sample` of the given loci and individuals with the groups and
+    /// settings of this one
+    ///
+    /// Every `Sample` derived from another starts from here, so that each
+    /// field is carried over in one place.
+    pub(crate) fn with_data(&self, loci: Loci, individuals: Individuals) -> Sample {
+        let mut matrix = AlleleMatrix::new();
+        matrix.dirty = true;
+        Sample {
+            loci,
+            groups: self.groups.clone(),
+            individuals,
+            matrix,
+            ploidy: self.ploidy,
+            locus_ploidy: self.locus_ploidy.clone(),
+            imputation: self.imputation,
+            random: self.random,
         }
     }
 
@@ -190,11 +706,38 @@ impl Sample {
     /// This function is called before a matrix calculation
     /// so there is no need to explicitly call it after observing data.
     pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
-
-        let loci: Vec<(usize, usize)> = self.loci.iter().enumerate().map(|(i, x)| {
-            (i, i + x.1.variations.lock().unwrap().len())
+        let mut start = 0;
+        let loci: Vec<(usize, usize)> = self.loci.values().map(|locus| {
+            let end = start + locus.variations.lock().unwrap().len();
+            let range = (start, end);
+            start = end;
+            range
         }).collect();
         self.matrix = AlleleMatrix::from_vec(self.individuals.len(), loci, Vec::<AlleleCount>::from(&*self))?;
+        self.matrix.individual_names = self.individuals.keys().cloned().collect();
+        self.matrix.variation_names = self
+            .loci
+            .values()
+            .flat_map(|locus| locus.variations.lock().unwrap().keys().cloned().collect::<Vec<_>>())
+            .collect();
+        self.matrix.repeat_lengths = self
+            .loci
+            .values()
+            .map(|locus| locus.annotation().repeat_length())
+            .collect();
+        self.matrix.allele_sizes = self
+            .loci
+            .values()
+            .flat_map(|locus| {
+                let hint = locus.hint();
+                let variations = locus.variations.lock().unwrap();
+                variations.keys().map(|name| hint.allele_size(name)).collect::<Vec<_>>()
+            })
+            .collect();
+        if let Imputation::Mean = self.imputation {
+            let rows: Vec<usize> = (0..self.individuals.len()).collect();
+            self.matrix.imputed = Some(self.matrix.population_frequency(&rows)?);
+        }
         Ok(())
     }
 
@@ -228,6 +771,15 @@ impl Sample {
         )
     }
 
+    /// Returns the allele in this locus, and sets the kind of marker the
+    /// locus holds
+    ///
+    /// This is `set_locus_hint()` followed by `allele()`.
+    pub fn hinted_allele(&mut self, locus: &str, variation: &str, hint: LocusHint) -> Allele {
+        self.set_locus_hint(locus, hint);
+        self.allele(locus, variation)
+    }
+
     /// Returns a reference to a `Group`
     ///
     /// This will create the `Group` if needed and mark the `Sample`
@@ -237,7 +789,7 @@ impl Sample {
             .entry(group.into())
             .or_insert({
                 self.matrix.dirty = true;
-                Arc::new(Group::new(group.into()))
+                Arc::new(Group::new(group))
             })
             .clone()
     }
@@ -250,14 +802,17 @@ impl Sample {
     pub fn _observe(&mut self, observation: Observation) {
         match &observation {
             Observation::Allele(individual, locus, variation) => {
-                let allele = self.allele(&locus, &variation);
-                *self
+                let allele = self.allele(locus, variation);
+                let individual = self
                     .individuals
                     .entry(individual.into())
-                    .or_insert(Individual::new(individual))
-                    .genome
-                    .entry(allele)
-                    .or_insert(0) += 1;
+                    .or_insert(Individual::new(individual));
+                individual
+                    .alleles
+                    .entry(locus.into())
+                    .or_default()
+                    .push(allele.1.clone());
+                *individual.genome.entry(allele).or_insert(0) += 1;
             }
             Observation::Group(individual, group) => {
                 let arc_group = self.group(group);
@@ -274,6 +829,27 @@ impl Sample {
                     .meta
                     .insert(meta.into(), content.into());
             }
+            Observation::Missing(individual, locus) => {
+                self.matrix.dirty = true;
+                self.loci
+                    .entry(locus.into())
+                    .or_insert_with(|| Arc::new(Locus::new(locus)));
+                self.individuals
+                    .entry(individual.into())
+                    .or_insert(Individual::new(individual))
+                    .missing
+                    .insert(locus.into());
+            }
+            Observation::Annotation(locus, annotation) => {
+                self.matrix.dirty = true;
+                self.loci
+                    .entry(locus.into())
+                    .or_insert_with(|| Arc::new(Locus::new(locus)))
+                    .annotation
+                    .lock()
+                    .unwrap()
+                    .set(annotation.clone());
+            }
         }
     }
 
@@ -288,11 +864,110 @@ impl Sample {
         Ok(())
     }
 
+    /// The named `Locus`, if it was observed
+    pub fn locus(&self, name: &str) -> Option<&Arc<Locus>> {
+        self.loci.get(name)
+    }
+
     /// A list of the names of all loci in a sample
     pub fn loci_names(&self) -> Vec<&String> {
         self.loci.keys().collect()
     }
 
+    /// A list of the names of all groups in a sample
+    pub fn group_names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.groups.keys().collect();
+        names.sort();
+        names
+    }
+
+    /// The rows of the `AlleleMatrix` holding the members of `group`
+    pub(crate) fn group_rows(&self, group: &str) -> Vec<usize> {
+        self.individuals
+            .values()
+            .enumerate()
+            .filter(|(_, individual)| individual.in_group(group))
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    /// Sets the ploidy of every `Individual` at every `Locus`
+    pub fn set_ploidy(&mut self, ploidy: usize) {
+        self.ploidy = Some(ploidy);
+    }
+
+    /// Sets the ploidy of every `Individual` at the named `Locus`
+    ///
+    /// This takes precedence over the ploidy of the `Sample`.
+    pub fn set_locus_ploidy(&mut self, locus: &str, ploidy: usize) {
+        self.locus_ploidy.insert(locus.into(), ploidy);
+    }
+
+    /// Sets the source of randomness of the stochastic analyses of this
+    /// `Sample` not given a seed of their own
+    pub fn set_random_source(&mut self, random: RandomSource) {
+        self.random = random;
+    }
+
+    pub fn random_source(&self) -> RandomSource {
+        self.random
+    }
+
+    /// Sets the kind of marker the named `Locus` holds
+    ///
+    /// This creates the `Locus` if needed.
+    pub fn set_locus_hint(&mut self, locus: &str, hint: LocusHint) {
+        self._observe(Observation::Annotation(locus.into(), Annotation::Hint(hint)));
+    }
+
+    /// Sets the ploidy of the named `Individual` at every `Locus`
+    ///
+    /// This takes precedence over the ploidy of the `Sample` and of
+    /// any `Locus`, and creates the `Individual` if needed.
+    pub fn set_individual_ploidy(&mut self, individual: &str, ploidy: usize) {
+        self.individuals
+            .entry(individual.into())
+            .or_insert(Individual::new(individual))
+            .ploidy = Some(ploidy);
+    }
+
+    /// The ploidy expected of an `Individual` at a `Locus`, if any was set
+    pub fn ploidy(&self, individual: &str, locus: &str) -> Option<usize> {
+        self.individuals
+            .get(individual)
+            .and_then(|individual| individual.ploidy)
+            .or_else(|| self.locus_ploidy.get(locus).copied())
+            .or(self.ploidy)
+    }
+
+    /// Checks that every typed genotype has as many alleles as its ploidy
+    ///
+    /// Genotypes without any allele and genotypes without a set ploidy
+    /// are not checked. The error lists every mismatch found.
+    pub fn validate_ploidy(&self) -> Result<(), Box<dyn Error>> {
+        let mut mismatches = vec![];
+        for (name, individual) in self.individuals.iter() {
+            for locus in self.loci.keys() {
+                let observed = match individual.genotype(locus) {
+                    Some(genotype) => genotype.ploidy(),
+                    None => 0,
+                };
+                match self.ploidy(name, locus) {
+                    Some(ploidy) if observed > 0 && observed != ploidy => mismatches.push(format!(
+                        "`{}` has {} alleles at `{}` but a ploidy of {}",
+                        name, observed, locus, ploidy
+                    )),
+                    _ => {}
+                }
+            }
+        }
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches.join("; ").into())
+        }
+    }
+
     pub fn variations(&self, locus: &str) -> Option<Vec<String>> {
         self.loci.get(locus).map(|loc| {
             loc.variations.lock().unwrap().keys().map(|x| x.to_string()).collect()
@@ -305,17 +980,59 @@ impl From<&Sample> for Vec<AlleleCount> {
         let mut v = vec![];
         for (_, individual) in sample.individuals.iter() {
             for (_, locus) in sample.loci.iter() {
-                for (_, variation) in locus.variations.lock().unwrap().iter() {
-                    v.push(
-                        match individual.genome.get(&(locus.clone(), variation.clone())) {
-                            None => 0,
-                            Some(c) => *c,
-                        },
-                    )
-                }
+                v.extend(individual.locus_counts(locus));
             }
         }
         v
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_genotypes_and_ploidy() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .name_field("name")
+                .from_reader(Box::new("name,a,b\nx,2/1,1\ny,1/1/2,2/2/2".as_bytes()))?,
+        )?;
+
+        let x = sample.individuals["x"].genotype("a").unwrap();
+        assert_eq!(x.ordered(), ["2", "1"]);
+        assert_eq!(x.unordered(), ["1", "2"]);
+        assert!(!x.is_homozygous());
+        let y = sample.individuals["y"].genotype("a").unwrap();
+        assert_eq!(y.ploidy(), 3);
+        assert_eq!(y.counts().get("1"), Some(&2));
+        assert!(sample.individuals["x"].genotype("c").is_none());
+
+        assert!(sample.validate_ploidy().is_ok());
+        sample.set_ploidy(2);
+        sample.set_locus_ploidy("b", 1);
+        assert_eq!(sample.ploidy("x", "b"), Some(1));
+        let errors = sample.validate_ploidy().unwrap_err().to_string();
+        assert!(errors.contains("`y` has 3 alleles at `a`"));
+        assert!(errors.contains("`y` has 3 alleles at `b`"));
+        assert!(!errors.contains("`x`"));
+
+        sample.set_individual_ploidy("y", 3);
+        assert!(sample.validate_ploidy().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hints_decide_allele_sizes() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        let (locus, _) = sample.hinted_allele("a", "12", LocusHint::Classical);
+        assert_eq!(locus.hint(), LocusHint::Classical);
+        sample._observe(Observation::Allele("x".into(), "b".into(), "12".into()));
+        sample.flush()?;
+        assert_eq!(sample.matrix.allele_sizes(), [None, Some(12.0)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use ndarray::{Array1, Array2};
+
+/// Eigen decomposition of a symmetric matrix by cyclic Jacobi rotations
+///
+/// Returns the eigenvalues in decreasing order and the matching unit
+/// eigenvectors as columns. Each eigenvector is signed so that its
+/// largest component is positive, which makes results reproducible.
+pub(crate) fn symmetric_eigen(matrix: &Array2<f64>) -> (Vec<f64>, Array2<f64>) {
+    let n = matrix.nrows();
+    let mut a = matrix.clone();
+    let mut v = Array2::<f64>::eye(n);
+    let scale: f64 = a.iter().map(|x| x * x).sum::<f64>().max(f64::MIN_POSITIVE);
+
+    for _ in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|p| ((p + 1)..n).map(move |q| (p, q)))
+            .map(|(p, q)| a[[p, q]] * a[[p, q]])
+            .sum();
+        if off_diagonal <= 1e-24 * scale {
+            break;
+        }
+        for p in 0..n {
+            for q in (p + 1)..n {
+                if a[[p, q]].abs() <= f64::MIN_POSITIVE {
+                    continue;
+                }
+                let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let t = if theta == 0.0 { 1.0 } else { t };
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[[k, p]], a[[k, q]]);
+                    a[[k, p]] = c * akp - s * akq;
+                    a[[k, q]] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+                    a[[p, k]] = c * apk - s * aqk;
+                    a[[q, k]] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[[k, p]], v[[k, q]]);
+                    v[[k, p]] = c * vkp - s * vkq;
+                    v[[k, q]] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|i, j| {
+        a[[*j, *j]]
+            .partial_cmp(&a[[*i, *i]])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let values = order.iter().map(|i| a[[*i, *i]]).collect();
+    let mut vectors = Array2::<f64>::zeros((n, n));
+    for (column, i) in order.iter().enumerate() {
+        let mut vector = v.column(*i).to_owned();
+        let largest = vector
+            .iter()
+            .fold(0.0, |m: f64, x| if x.abs() > m.abs() { *x } else { m });
+        if largest < 0.0 {
+            vector.mapv_inplace(|x| -x);
+        }
+        vectors.column_mut(column).assign(&vector);
+    }
+    (values, vectors)
+}
+
+/// The lower triangular Cholesky factor of a symmetric matrix
+///
+/// Returns `None` when the matrix is not positive definite.
+pub(crate) fn cholesky(matrix: &Array2<f64>) -> Option<Array2<f64>> {
+    let n = matrix.nrows();
+    let mut l = Array2::<f64>::zeros((n, n));
+    for i in 0..n {
+        for j in 0..=i {
+            let sum: f64 = (0..j).map(|k| l[[i, k]] * l[[j, k]]).sum();
+            if i == j {
+                let d = matrix[[i, i]] - sum;
+                if d <= 0.0 {
+                    return None;
+                }
+                l[[i, i]] = d.sqrt();
+            } else {
+                l[[i, j]] = (matrix[[i, j]] - sum) / l[[j, j]];
+            }
+        }
+    }
+    Some(l)
+}
+
+/// Solves `L x = b` for a lower triangular `L`
+pub(crate) fn forward_substitute(l: &Array2<f64>, b: &Array1<f64>) -> Array1<f64> {
+    let mut x = Array1::<f64>::zeros(b.len());
+    for i in 0..b.len() {
+        let sum: f64 = (0..i).map(|k| l[[i, k]] * x[k]).sum();
+        x[i] = (b[i] - sum) / l[[i, i]];
+    }
+    x
+}
+
+/// Solves `L^T x = b` for a lower triangular `L`
+pub(crate) fn backward_substitute(l: &Array2<f64>, b: &Array1<f64>) -> Array1<f64> {
+    let mut x = Array1::<f64>::zeros(b.len());
+    for i in (0..b.len()).rev() {
+        let sum: f64 = ((i + 1)..b.len()).map(|k| l[[k, i]] * x[k]).sum();
+        x[i] = (b[i] - sum) / l[[i, i]];
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symmetric_eigen_reconstructs_matrix() {
+        let a = ndarray::arr2(&[[4.0, 1.0, 2.0], [1.0, 3.0, 0.5], [2.0, 0.5, 5.0]]);
+        let (values, vectors) = symmetric_eigen(&a);
+        assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+        let diagonal = Array2::from_diag(&Array1::from(values));
+        let reconstructed = vectors.dot(&diagonal).dot(&vectors.t());
+        assert!(reconstructed
+            .iter()
+            .zip(a.iter())
+            .all(|(x, y)| (x - y).abs() < 1e-10));
+    }
+
+    #[test]
+    fn test_cholesky_solves_triangular_systems() {
+        let a = ndarray::arr2(&[[4.0, 1.0, 2.0], [1.0, 3.0, 0.5], [2.0, 0.5, 5.0]]);
+        let l = cholesky(&a).unwrap();
+        assert!(l
+            .dot(&l.t())
+            .iter()
+            .zip(a.iter())
+            .all(|(x, y)| (x - y).abs() < 1e-10));
+        let b = ndarray::arr1(&[1.0, 2.0, 3.0]);
+        let x = backward_substitute(&l, &forward_substitute(&l, &b));
+        assert!(a
+            .dot(&x)
+            .iter()
+            .zip(b.iter())
+            .all(|(x, y)| (x - y).abs() < 1e-10));
+        assert!(cholesky(&ndarray::arr2(&[[1.0, 2.0], [2.0, 1.0]])).is_none());
+    }
+}
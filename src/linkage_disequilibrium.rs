@@ -0,0 +1,418 @@
+use crate::prelude::*;
+use crate::PairwiseMatrix;
+use std::error::Error;
+use std::io::Write;
+
+/// Linkage disequilibrium between every pair of loci
+///
+/// Unphased genotypes give Weir's composite disequilibrium, estimated from
+/// the covariance of allele frequencies within individuals, which needs no
+/// assumption of Hardy-Weinberg equilibrium. Phased genotypes pair the
+/// alleles in observation order into haplotypes and give the gametic
+/// disequilibrium. Only individuals typed at both loci are counted.
+pub struct PairwiseLd {
+    per_group: bool,
+    phased: bool,
+}
+
+impl Default for PairwiseLd {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PairwiseLd {
+    /// Construct a new unphased calculation over the whole `Sample`
+    pub fn new() -> Self {
+        Self {
+            per_group: false,
+            phased: false,
+        }
+    }
+
+    /// Whether to calculate within each `Group` rather than over the whole `Sample`
+    pub fn per_group(&mut self, per_group: bool) -> &mut Self {
+        self.per_group = per_group;
+        self
+    }
+
+    /// Whether the alleles of every genotype are in haplotype order
+    pub fn phased(&mut self, phased: bool) -> &mut Self {
+        self.phased = phased;
+        self
+    }
+}
+
+/// The linkage disequilibrium between one pair of loci
+///
+/// Loci with more than two variations are summarized over every pair of
+/// variations, weighting each by the product of their frequencies, so
+/// that biallelic loci give the usual values.
+pub struct LinkageRecord {
+    group: Option<String>,
+    loci: (String, String),
+    individuals: usize,
+    d: f32,
+    d_prime: f32,
+    r_squared: f32,
+}
+
+impl LinkageRecord {
+    /// The `Group` calculated, or `None` for the whole `Sample`
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub fn loci(&self) -> (&str, &str) {
+        (&self.loci.0, &self.loci.1)
+    }
+
+    /// The number of individuals typed at both loci
+    pub fn individuals(&self) -> usize {
+        self.individuals
+    }
+
+    /// The magnitude of the disequilibrium coefficient `D`
+    pub fn d(&self) -> f32 {
+        self.d
+    }
+
+    /// Lewontin's `D'`, `D` relative to its maximum given allele frequencies
+    pub fn d_prime(&self) -> f32 {
+        self.d_prime
+    }
+
+    /// The squared correlation between alleles, `r^2`
+    pub fn r_squared(&self) -> f32 {
+        self.r_squared
+    }
+}
+
+/// The linkage disequilibrium of every pair of loci
+pub struct LinkageTable {
+    loci: Vec<String>,
+    records: Vec<LinkageRecord>,
+}
+
+impl LinkageTable {
+    pub fn records(&self) -> &[LinkageRecord] {
+        &self.records
+    }
+
+    /// The `r^2` of every pair of loci in `group`, with ones on the diagonal
+    pub fn r_squared_matrix(&self, group: Option<&str>) -> PairwiseMatrix {
+        self.matrix(group, |record| record.r_squared)
+    }
+
+    /// The `D'` of every pair of loci in `group`, with ones on the diagonal
+    pub fn d_prime_matrix(&self, group: Option<&str>) -> PairwiseMatrix {
+        self.matrix(group, |record| record.d_prime)
+    }
+
+    fn matrix(&self, group: Option<&str>, statistic: fn(&LinkageRecord) -> f32) -> PairwiseMatrix {
+        let mut data = ndarray::Array2::<f32>::eye(self.loci.len());
+        for record in self.records.iter().filter(|r| r.group.as_deref() == group) {
+            let i = self.loci.iter().position(|l| *l == record.loci.0).unwrap();
+            let j = self.loci.iter().position(|l| *l == record.loci.1).unwrap();
+            data[[i, j]] = statistic(record);
+            data[[j, i]] = statistic(record);
+        }
+        PairwiseMatrix {
+            labels: self.loci.clone(),
+            data,
+        }
+    }
+
+    /// Writes one comma separated row per record, preceded by a header
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "group",
+            "locus_a",
+            "locus_b",
+            "individuals",
+            "d",
+            "d_prime",
+            "r_squared",
+        ])?;
+        for record in self.records.iter() {
+            wtr.write_record(&[
+                record.group.clone().unwrap_or_default(),
+                record.loci.0.clone(),
+                record.loci.1.clone(),
+                record.individuals.to_string(),
+                record.d.to_string(),
+                record.d_prime.to_string(),
+                record.r_squared.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+pub trait LinkageDisequilibrium {
+    /// Calculates the linkage disequilibrium between every pair of loci
+    fn linkage_disequilibrium(&mut self, ld: &PairwiseLd) -> Result<LinkageTable, Box<dyn Error>>;
+}
+
+/// The allele frequencies of two loci and the disequilibrium of every
+/// pair of their variations
+struct Disequilibrium {
+    individuals: usize,
+    p: Vec<f64>,
+    q: Vec<f64>,
+    d: Vec<Vec<f64>>,
+}
+
+impl Disequilibrium {
+    /// Composite disequilibrium from the allele frequencies of individuals
+    fn composite(x: &[Vec<f64>], y: &[Vec<f64>], copies: f64) -> Disequilibrium {
+        let n = x.len() as f64;
+        let mean = |rows: &[Vec<f64>], u: usize| rows.iter().map(|row| row[u]).sum::<f64>() / n;
+        let p: Vec<f64> = (0..x.first().map_or(0, |r| r.len()))
+            .map(|u| mean(x, u))
+            .collect();
+        let q: Vec<f64> = (0..y.first().map_or(0, |r| r.len()))
+            .map(|v| mean(y, v))
+            .collect();
+        let d = p
+            .iter()
+            .enumerate()
+            .map(|(u, pu)| {
+                q.iter()
+                    .enumerate()
+                    .map(|(v, qv)| {
+                        let covariance: f64 = x
+                            .iter()
+                            .zip(y.iter())
+                            .map(|(x, y)| (x[u] - pu) * (y[v] - qv))
+                            .sum::<f64>()
+                            / n;
+                        copies * covariance
+                    })
+                    .collect()
+            })
+            .collect();
+        Disequilibrium {
+            individuals: x.len(),
+            p,
+            q,
+            d,
+        }
+    }
+
+    /// Gametic disequilibrium from haplotypes of variation indices
+    fn gametic(
+        haplotypes: &[(usize, usize)],
+        individuals: usize,
+        a: usize,
+        b: usize,
+    ) -> Disequilibrium {
+        let n = haplotypes.len() as f64;
+        let mut joint = vec![vec![0.0; b]; a];
+        for (u, v) in haplotypes {
+            joint[*u][*v] += 1.0 / n;
+        }
+        let p: Vec<f64> = joint.iter().map(|row| row.iter().sum()).collect();
+        let q: Vec<f64> = (0..b)
+            .map(|v| joint.iter().map(|row| row[v]).sum())
+            .collect();
+        let d = (0..a)
+            .map(|u| (0..b).map(|v| joint[u][v] - p[u] * q[v]).collect())
+            .collect();
+        Disequilibrium {
+            individuals,
+            p,
+            q,
+            d,
+        }
+    }
+
+    fn record(&self, group: Option<String>, loci: (String, String)) -> LinkageRecord {
+        let (mut d, mut d_prime, mut r_squared, mut weight) = (0.0, 0.0, 0.0, 0.0);
+        for (u, pu) in self.p.iter().enumerate() {
+            for (v, qv) in self.q.iter().enumerate() {
+                let variance = pu * (1.0 - pu) * qv * (1.0 - qv);
+                if variance <= 0.0 {
+                    continue;
+                }
+                let duv = self.d[u][v];
+                let d_max = if duv > 0.0 {
+                    (pu * (1.0 - qv)).min((1.0 - pu) * qv)
+                } else {
+                    (pu * qv).min((1.0 - pu) * (1.0 - qv))
+                };
+                weight += pu * qv;
+                d += pu * qv * duv.abs();
+                d_prime += pu * qv * (duv.abs() / d_max).min(1.0);
+                r_squared += pu * qv * (duv * duv / variance).min(1.0);
+            }
+        }
+        let summary = |x: f64| {
+            if weight > 0.0 {
+                (x / weight) as f32
+            } else {
+                f32::NAN
+            }
+        };
+        LinkageRecord {
+            group,
+            loci,
+            individuals: self.individuals,
+            d: summary(d),
+            d_prime: summary(d_prime),
+            r_squared: summary(r_squared),
+        }
+    }
+}
+
+impl LinkageDisequilibrium for Sample {
+    fn linkage_disequilibrium(&mut self, ld: &PairwiseLd) -> Result<LinkageTable, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let groups: Vec<Option<String>> = if ld.per_group {
+            self.group_names().into_iter().cloned().map(Some).collect()
+        } else {
+            vec![None]
+        };
+        let loci: Vec<String> = self.loci.keys().cloned().collect();
+        let variations: Vec<Vec<String>> = self
+            .loci
+            .values()
+            .map(|locus| locus.variations.lock().unwrap().keys().cloned().collect())
+            .collect();
+
+        let all: Vec<&Individual> = self.individuals.values().collect();
+
+        let mut records = vec![];
+        for group in groups {
+            let rows: Vec<usize> = match &group {
+                Some(group) => self.group_rows(group),
+                None => (0..self.matrix.individuals()).collect(),
+            };
+            let individuals: Vec<&Individual> = rows.iter().map(|row| all[*row]).collect();
+            for a in 0..loci.len() {
+                for b in (a + 1)..loci.len() {
+                    let disequilibrium = if ld.phased {
+                        let mut haplotypes = vec![];
+                        let mut typed = 0;
+                        for individual in individuals.iter() {
+                            if let (Some(x), Some(y)) =
+                                (individual.genotype(&loci[a]), individual.genotype(&loci[b]))
+                            {
+                                if x.ploidy() != y.ploidy() {
+                                    continue;
+                                }
+                                typed += 1;
+                                let index = |l: usize, allele: &String| {
+                                    variations[l]
+                                        .iter()
+                                        .position(|name| name == allele)
+                                        .ok_or_else(|| {
+                                            format!(
+                                                "allele `{}` is not a variation of locus `{}`",
+                                                allele, loci[l]
+                                            )
+                                        })
+                                };
+                                for (u, v) in x.ordered().iter().zip(y.ordered().iter()) {
+                                    haplotypes.push((index(a, u)?, index(b, v)?));
+                                }
+                            }
+                        }
+                        Disequilibrium::gametic(
+                            &haplotypes,
+                            typed,
+                            variations[a].len(),
+                            variations[b].len(),
+                        )
+                    } else {
+                        let (x, y, copies) = self.typed_frequencies(&rows, a, b);
+                        Disequilibrium::composite(&x, &y, copies)
+                    };
+                    records.push(
+                        disequilibrium.record(group.clone(), (loci[a].clone(), loci[b].clone())),
+                    );
+                }
+            }
+        }
+        Ok(LinkageTable { loci, records })
+    }
+}
+
+impl Sample {
+    /// The allele frequencies at loci `a` and `b` of the `rows` typed at
+    /// both, and their mean number of allele copies per locus
+    fn typed_frequencies(
+        &self,
+        rows: &[usize],
+        a: usize,
+        b: usize,
+    ) -> (Vec<Vec<f64>>, Vec<Vec<f64>>, f64) {
+        let matrix = &self.matrix;
+        let (mut x, mut y, mut copies) = (vec![], vec![], 0.0);
+        for row in rows {
+            let counts = |locus: usize| {
+                let (start, end) = matrix.loci[locus];
+                matrix.data.slice(ndarray::s![*row, start..end]).to_vec()
+            };
+            let (ca, cb) = (counts(a), counts(b));
+            let (ta, tb) = (ca.iter().sum::<u32>(), cb.iter().sum::<u32>());
+            if ta == 0 || tb == 0 {
+                continue;
+            }
+            copies += (ta + tb) as f64 / 2.0;
+            x.push(ca.iter().map(|c| *c as f64 / ta as f64).collect());
+            y.push(cb.iter().map(|c| *c as f64 / tb as f64).collect());
+        }
+        let copies = copies / x.len().max(1) as f64;
+        (x, y, copies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_linked_and_unlinked_loci() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().group_field("pop").from_reader(Box::new(
+            "pop,a,b,c\nx,1/1,5/5,1/2\nx,1/2,5/6,1/1\nx,2/2,6/6,1/2\ny,1/1,5/5,2/2\ny,2/2,6/6,1/2\ny,1/2,5/6,1/1"
+                .as_bytes(),
+        ))?)?;
+
+        let table = sample.linkage_disequilibrium(&PairwiseLd::new())?;
+        assert_eq!(table.records().len(), 3);
+        let ab = &table.records()[0];
+        assert_eq!(ab.loci(), ("a", "b"));
+        assert_eq!(ab.individuals(), 6);
+        assert!((ab.r_squared() - 1.0).abs() < 1e-6);
+        assert!((ab.d_prime() - 1.0).abs() < 1e-6);
+        let r_squared = table.r_squared_matrix(None);
+        assert!(r_squared.get("a", "c").unwrap() < 0.5);
+        assert_eq!(r_squared.get("c", "c"), Some(1.0));
+
+        let per_group = sample.linkage_disequilibrium(PairwiseLd::new().per_group(true))?;
+        assert_eq!(per_group.records().len(), 6);
+        assert_eq!(per_group.records()[3].group(), Some("y"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_phased_haplotypes() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new().from_reader(Box::new("a,b\n1/2,1/2\n2/1,2/1".as_bytes()))?,
+        )?;
+        let composite = sample.linkage_disequilibrium(&PairwiseLd::new())?;
+        assert!(composite.records()[0].r_squared().abs() < 1e-6);
+        let phased = sample.linkage_disequilibrium(PairwiseLd::new().phased(true))?;
+        assert!((phased.records()[0].r_squared() - 1.0).abs() < 1e-6);
+        assert!((phased.records()[0].d() - 0.25).abs() < 1e-6);
+        Ok(())
+    }
+}
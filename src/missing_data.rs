@@ -0,0 +1,334 @@
+use crate::prelude::*;
+use crate::{AlleleCount, Individuals, Loci};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/// How the missing genotypes left after filtering are treated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Imputation {
+    /// Missing genotypes are left out of every statistic
+    Ignore,
+    /// Missing genotypes take the mean allele frequencies of their locus
+    ///
+    /// Only frequency based analyses of individuals, such as distances
+    /// and the index of association, can use such fractional genotypes.
+    /// Count based statistics and population frequencies keep ignoring
+    /// them.
+    Mean,
+    /// Missing genotypes take the most common genotype of their locus
+    Mode,
+}
+
+/// The number of missing genotypes of one `Locus` or `Individual`
+pub struct Missingness {
+    name: String,
+    missing: usize,
+    genotypes: usize,
+}
+
+impl Missingness {
+    /// The name of the `Locus` or `Individual`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn missing(&self) -> usize {
+        self.missing
+    }
+
+    /// The number of genotypes, missing or not
+    pub fn genotypes(&self) -> usize {
+        self.genotypes
+    }
+
+    /// The proportion of genotypes that are missing
+    pub fn rate(&self) -> f32 {
+        self.missing as f32 / self.genotypes as f32
+    }
+}
+
+/// Missingness of every `Locus` and every `Individual`
+pub struct MissingnessReport {
+    loci: Vec<Missingness>,
+    individuals: Vec<Missingness>,
+}
+
+impl MissingnessReport {
+    pub fn loci(&self) -> &[Missingness] {
+        &self.loci
+    }
+
+    pub fn individuals(&self) -> &[Missingness] {
+        &self.individuals
+    }
+
+    /// Writes one comma separated row per locus then per individual,
+    /// preceded by a header
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(["level", "name", "missing", "genotypes", "rate"])?;
+        let levels = [("locus", &self.loci), ("individual", &self.individuals)];
+        for (level, rows) in levels.iter() {
+            for row in rows.iter() {
+                wtr.write_record(&[
+                    level.to_string(),
+                    row.name.clone(),
+                    row.missing.to_string(),
+                    row.genotypes.to_string(),
+                    row.rate().to_string(),
+                ])?;
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Drops loci and individuals missing too many genotypes and imputes the rest
+pub struct MissingDataFilter {
+    max_locus_missingness: f32,
+    max_individual_missingness: f32,
+    imputation: Imputation,
+}
+
+impl Default for MissingDataFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MissingDataFilter {
+    /// Construct a new filter that keeps everything and imputes nothing
+    pub fn new() -> Self {
+        Self {
+            max_locus_missingness: 1.0,
+            max_individual_missingness: 1.0,
+            imputation: Imputation::Ignore,
+        }
+    }
+
+    /// Loci with a larger proportion of missing genotypes are dropped
+    pub fn max_locus_missingness(&mut self, max_locus_missingness: f32) -> &mut Self {
+        self.max_locus_missingness = max_locus_missingness;
+        self
+    }
+
+    /// Individuals with a larger proportion of missing genotypes among
+    /// the loci kept are dropped
+    ///
+    /// When no locus is kept every individual counts as entirely missing.
+    pub fn max_individual_missingness(&mut self, max_individual_missingness: f32) -> &mut Self {
+        self.max_individual_missingness = max_individual_missingness;
+        self
+    }
+
+    pub fn imputation(&mut self, imputation: Imputation) -> &mut Self {
+        self.imputation = imputation;
+        self
+    }
+}
+
+pub trait MissingData {
+    /// Counts the missing genotypes of every `Locus` and `Individual`
+    fn missingness(&self) -> MissingnessReport;
+
+    /// Returns a new `Sample` filtered and imputed as set by `filter`
+    fn filter_missing(&self, filter: &MissingDataFilter) -> Sample;
+}
+
+impl MissingData for Sample {
+    fn missingness(&self) -> MissingnessReport {
+        let count = |name: &String, missing: usize, genotypes: usize| Missingness {
+            name: name.clone(),
+            missing,
+            genotypes,
+        };
+        MissingnessReport {
+            loci: self
+                .loci
+                .keys()
+                .map(|locus| {
+                    let missing = self
+                        .individuals
+                        .values()
+                        .filter(|i| i.is_missing(locus))
+                        .count();
+                    count(locus, missing, self.individuals.len())
+                })
+                .collect(),
+            individuals: self
+                .individuals
+                .values()
+                .map(|individual| {
+                    let missing = self
+                        .loci
+                        .keys()
+                        .filter(|l| individual.is_missing(l))
+                        .count();
+                    count(&individual.name, missing, self.loci.len())
+                })
+                .collect(),
+        }
+    }
+
+    fn filter_missing(&self, filter: &MissingDataFilter) -> Sample {
+        let loci: Loci = self
+            .loci
+            .iter()
+            .zip(self.missingness().loci.iter())
+            .filter(|(_, missingness)| missingness.rate() <= filter.max_locus_missingness)
+            .map(|((name, locus), _)| (name.clone(), locus.clone()))
+            .collect();
+
+        let mut individuals = Individuals::new();
+        for (name, individual) in self.individuals.iter() {
+            let missing = loci.keys().filter(|l| individual.is_missing(l)).count();
+            // Without any locus kept, every individual is entirely missing.
+            let rate = if loci.is_empty() {
+                1.0
+            } else {
+                missing as f32 / loci.len() as f32
+            };
+            if rate > filter.max_individual_missingness {
+                continue;
+            }
+            let mut individual = individual.clone();
+            individual
+                .genome
+                .retain(|(locus, _), _| loci.contains_key(&locus.name));
+            individual
+                .alleles
+                .retain(|locus, _| loci.contains_key(locus));
+            individual.missing.retain(|locus| loci.contains_key(locus));
+            individuals.insert(name.clone(), individual);
+        }
+
+        if filter.imputation == Imputation::Mode {
+            for locus in loci.values() {
+                impute_mode(&mut individuals, locus);
+            }
+        }
+
+        let mut sample = self.with_data(loci, individuals);
+        sample.imputation = filter.imputation;
+        sample
+    }
+}
+
+/// Gives every individual missing `locus` its most common genotype
+///
+/// Ties are broken in favour of the genotype carrying most copies of
+/// the first variations.
+fn impute_mode(individuals: &mut Individuals, locus: &Arc<Locus>) {
+    let mut genotypes: BTreeMap<Vec<AlleleCount>, usize> = BTreeMap::new();
+    for individual in individuals.values().filter(|i| !i.is_missing(&locus.name)) {
+        *genotypes.entry(individual.locus_counts(locus)).or_insert(0) += 1;
+    }
+    let mode = match genotypes
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)))
+    {
+        Some((mode, _)) => mode,
+        None => return,
+    };
+
+    let variations: Vec<Arc<Variation>> =
+        locus.variations.lock().unwrap().values().cloned().collect();
+    for individual in individuals
+        .values_mut()
+        .filter(|i| i.is_missing(&locus.name))
+    {
+        individual.missing.remove(&locus.name);
+        individual.genome.retain(|(l, _), _| l != locus);
+        let alleles = individual.alleles.entry(locus.name.clone()).or_default();
+        alleles.clear();
+        for (variation, count) in variations.iter().zip(mode.iter()).filter(|(_, c)| **c > 0) {
+            individual
+                .genome
+                .insert((locus.clone(), variation.clone()), *count);
+            alleles.extend(std::iter::repeat_n(variation.clone(), *count as usize));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::{IndividualDistance, Prevosti};
+    use crate::index_of_association::IndexOfAssociation;
+    use crate::observable::CsvBuilder;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .name_field("name")
+                .missing_codes(["0", "?"].iter().map(|s| s.to_string()).collect())
+                .from_reader(Box::new(
+                    "name,a,b,c\nw,1/1,1/2,?\nx,1/2,0/0,?\ny,1/1,2/2,1/1\nz,?,2/2,?".as_bytes(),
+                ))?,
+        )?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_missingness_and_filtering() -> Result<(), Box<dyn Error>> {
+        let sample = sample()?;
+        assert_eq!(sample.variations("b").unwrap(), vec!["1", "2"]);
+        let report = sample.missingness();
+        let rates: Vec<f32> = report.loci().iter().map(|m| m.rate()).collect();
+        assert_eq!(rates, vec![0.25, 0.25, 0.75]);
+        let missing: Vec<usize> = report.individuals().iter().map(|m| m.missing()).collect();
+        assert_eq!(missing, vec![1, 2, 0, 2]);
+
+        let filtered = sample.filter_missing(
+            MissingDataFilter::new()
+                .max_locus_missingness(0.5)
+                .max_individual_missingness(0.4),
+        );
+        assert_eq!(filtered.loci_names(), vec!["a", "b"]);
+        assert_eq!(
+            filtered.individuals.keys().collect::<Vec<_>>(),
+            vec!["w", "y"]
+        );
+
+        // Without loci, individuals are only kept when allowed to be entirely missing.
+        let mut filter = MissingDataFilter::new();
+        filter.max_locus_missingness(0.1);
+        assert_eq!(sample.filter_missing(&filter).individuals.len(), 4);
+        let mut filtered = sample.filter_missing(filter.max_individual_missingness(0.9));
+        assert!(filtered.loci_names().is_empty());
+        assert!(filtered.individuals.is_empty());
+        assert!(filtered.index_of_association().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_imputation() -> Result<(), Box<dyn Error>> {
+        let sample = sample()?;
+        let mut filter = MissingDataFilter::new();
+        filter
+            .max_locus_missingness(0.5)
+            .imputation(Imputation::Mode);
+        let moded = sample.filter_missing(&filter);
+        assert_eq!(
+            moded.individuals["z"].genotype("a").unwrap().ordered(),
+            ["1", "1"]
+        );
+        assert_eq!(
+            moded.individuals["x"].genotype("b").unwrap().ordered(),
+            ["2", "2"]
+        );
+
+        let mut meaned = sample.filter_missing(filter.imputation(Imputation::Mean));
+        assert!(meaned.individuals["z"].genotype("a").is_none());
+        meaned.flush()?;
+        let z = meaned.matrix.frequency()?;
+        assert!((z[[3, 0]] - 5.0 / 6.0).abs() < 1e-6);
+        let prevosti = meaned.distance_matrix(&Prevosti)?;
+        assert!((prevosti.get("y", "z").unwrap() - 1.0 / 12.0).abs() < 1e-6);
+        Ok(())
+    }
+}
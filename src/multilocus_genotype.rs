@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use crate::{AlleleCount, Individual, Individuals};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A multilocus genotype (MLG) and the individuals that share it
+///
+/// Two individuals share an MLG when they carry exactly the same alleles,
+/// with the same counts, at every locus. A missing genotype is not a
+/// wildcard: it only matches another missing genotype, so individuals that
+/// differ only in which loci are missing get different MLGs. Treating it as
+/// a wildcard would not be transitive, since one individual missing a locus
+/// could match two individuals that differ there. Filter or impute missing
+/// data first to group such individuals together.
+pub struct Mlg {
+    id: usize,
+    individuals: Vec<String>,
+}
+
+impl Mlg {
+    /// The index of this MLG, in order of the first individual carrying it
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The names of the individuals carrying this MLG
+    pub fn individuals(&self) -> &[String] {
+        &self.individuals
+    }
+}
+
+type GenotypeKey = Vec<(String, String, AlleleCount)>;
+
+impl Individual {
+    /// A key that is equal for individuals with identical genomes
+    fn genotype_key(&self) -> GenotypeKey {
+        let mut key: GenotypeKey = self
+            .genome
+            .iter()
+            .filter(|((locus, _), count)| **count > 0 && !self.missing.contains(&locus.name))
+            .map(|((locus, variation), count)| (locus.name.clone(), variation.name.clone(), *count))
+            .collect();
+        key.sort();
+        key
+    }
+
+    /// The sorted names of the groups this individual belongs to
+    fn group_key(&self) -> Vec<String> {
+        let mut key: Vec<String> = self.groups.iter().map(|group| group.name.clone()).collect();
+        key.sort();
+        key
+    }
+}
+
+pub trait MultilocusGenotypes {
+    /// Groups individuals with identical genomes into multilocus genotypes
+    fn mlgs(&self) -> Vec<Mlg>;
+
+    /// Returns a clone corrected copy of this `Sample`
+    ///
+    /// Only the first individual, by name, of every MLG is kept. When
+    /// `stratified` is true one individual is kept per MLG in every
+    /// combination of `Group`s, so that an MLG shared between groups is
+    /// still counted once in each of them.
+    fn clone_correct(&self, stratified: bool) -> Sample;
+}
+
+impl MultilocusGenotypes for Sample {
+    fn mlgs(&self) -> Vec<Mlg> {
+        let mut ids: BTreeMap<GenotypeKey, usize> = BTreeMap::new();
+        let mut mlgs: Vec<Mlg> = vec![];
+        for (name, individual) in self.individuals.iter() {
+            let id = *ids.entry(individual.genotype_key()).or_insert_with(|| {
+                mlgs.push(Mlg {
+                    id: mlgs.len(),
+                    individuals: vec![],
+                });
+                mlgs.len() - 1
+            });
+            mlgs[id].individuals.push(name.clone());
+        }
+        mlgs
+    }
+
+    fn clone_correct(&self, stratified: bool) -> Sample {
+        let mut seen: BTreeSet<(GenotypeKey, Vec<String>)> = BTreeSet::new();
+        let mut individuals = Individuals::new();
+        for (name, individual) in self.individuals.iter() {
+            let groups = if stratified {
+                individual.group_key()
+            } else {
+                vec![]
+            };
+            if seen.insert((individual.genotype_key(), groups)) {
+                individuals.insert(name.clone(), individual.clone());
+            }
+        }
+
+        self.with_data(self.loci.clone(), individuals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+    use std::error::Error;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .name_field("name")
+                .group_field("pop")
+                .from_reader(Box::new(
+                    "name,pop,a,b\nx,north,1/1,2/2\ny,south,1/1,2/2\nz,south,1/2,2/2\nw,south,1/1,2/2"
+                        .as_bytes(),
+                ))?,
+        )?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_mlgs_group_identical_individuals() -> Result<(), Box<dyn Error>> {
+        let mlgs = sample()?.mlgs();
+        assert_eq!(mlgs.len(), 2);
+        assert_eq!(mlgs[0].individuals(), ["w", "x", "y"]);
+        assert_eq!(mlgs[1].individuals(), ["z"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_genotypes_are_not_wildcards() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .name_field("name")
+                .missing_codes(["?"].iter().map(|s| s.to_string()).collect())
+                .from_reader(Box::new("name,a,b\nx,1/1,2/2\ny,1/1,?\nz,1/1,?".as_bytes()))?,
+        )?;
+        let mlgs = sample.mlgs();
+        assert_eq!(mlgs.len(), 2);
+        assert_eq!(mlgs[0].individuals(), ["x"]);
+        assert_eq!(mlgs[1].individuals(), ["y", "z"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_correct_keeps_one_individual_per_mlg() -> Result<(), Box<dyn Error>> {
+        let sample = sample()?;
+        assert_eq!(sample.clone_correct(false).individuals.len(), 2);
+        assert_eq!(sample.clone_correct(true).individuals.len(), 3);
+        Ok(())
+    }
+}
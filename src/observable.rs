@@ -1,13 +1,26 @@
 use crate::prelude::*;
 use csv;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::io::Read;
 
+mod annotation;
+mod genepop;
+mod plink;
+mod structure;
+mod vcf;
+
+pub use annotation::{LocusAnnotations, LocusAnnotationsBuilder};
+pub use genepop::{GenePop, GenePopBuilder, GenePopWriter};
+pub use plink::{Plink, PlinkBuilder, PlinkWriter};
+pub use structure::{Structure, StructureBuilder, StructureWriter};
+pub use vcf::{Vcf, VcfBuilder};
+
 enum ObservationPartial {
     Allele(String, String),
     Group(String),
     Meta(String, String),
+    Missing(String),
 }
 
 impl ObservationPartial {
@@ -24,6 +37,7 @@ impl ObservationPartial {
                 meta.to_string(),
                 content.to_string(),
             ),
+            Self::Missing(locus) => Observation::Missing(individual.to_string(), locus.to_string()),
         }
     }
 }
@@ -39,12 +53,17 @@ enum Field {
 
 /// Produces Observations from u8 delimted data
 ///
-/// `Csv` implements Iterator so it can be passed
-/// directly to `Sample::observe()`
+/// A genotype is observed as missing when any of its alleles is a
+/// missing code. Loci are annotated with their `LocusHint` along with the
+/// first row. `Csv` implements Iterator so it can be passed directly to
+/// `Sample::observe()`
 pub struct Csv {
     records: std::iter::Enumerate<csv::StringRecordsIntoIter<Box<dyn Read>>>,
     fields: Option<Vec<Field>>,
     separator: String,
+    missing_codes: HashSet<String>,
+    hint: Option<LocusHint>,
+    locus_hints: HashMap<String, LocusHint>,
     observation_buffer: VecDeque<Observation>,
     group_presence_identifier: String,
 }
@@ -54,23 +73,52 @@ impl Csv {
         records: csv::StringRecordsIntoIter<Box<dyn Read>>,
         fields: Option<Vec<Field>>,
         separator: &str,
+        missing_codes: &HashSet<String>,
+        hint: Option<LocusHint>,
+        locus_hints: &HashMap<String, LocusHint>,
         group_presence_identifier: &str,
     ) -> Self {
         Self {
             records: records.into_iter().enumerate(),
             fields,
             separator: separator.to_owned(),
+            missing_codes: missing_codes.clone(),
+            hint,
+            locus_hints: locus_hints.clone(),
             observation_buffer: VecDeque::new(),
             group_presence_identifier: group_presence_identifier.to_owned(),
         }
     }
 }
 
+impl Csv {
+    /// The partial observations of one genotype field
+    fn genotype(&self, locus: &str, field: &str) -> Vec<ObservationPartial> {
+        if field.split(&self.separator).any(|x| self.missing_codes.contains(x)) {
+            return vec![ObservationPartial::Missing(locus.into())];
+        }
+        field
+            .split(&self.separator)
+            .map(|x| ObservationPartial::Allele(locus.into(), x.into()))
+            .collect()
+    }
+
+    /// The hint observations of the loci
+    fn hints(&self, loci: Vec<String>) -> Vec<Observation> {
+        loci.into_iter()
+            .filter_map(|locus| {
+                let hint = self.locus_hints.get(&locus).copied().or(self.hint)?;
+                Some(Observation::Annotation(locus, Annotation::Hint(hint)))
+            })
+            .collect()
+    }
+}
+
 impl Iterator for Csv {
     type Item = Result<Observation, Box<dyn Error>>;
 
     fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
-        if self.observation_buffer.len() == 0 {
+        if self.observation_buffer.is_empty() {
             match self.records.next() {
                 None => {
                     return None;
@@ -85,10 +133,7 @@ impl Iterator for Csv {
                                     individual = field.to_string();
                                 }
                                 Field::Locus(s) => {
-                                    for x in field.split(&self.separator) {
-                                        partials
-                                            .push(ObservationPartial::Allele(s.into(), x.into()));
-                                    }
+                                    partials.extend(self.genotype(s, field));
                                 }
                                 Field::Group => {
                                     partials.push(ObservationPartial::Group(field.into()));
@@ -105,17 +150,30 @@ impl Iterator for Csv {
                         }
                     } else {
                         for (i, field) in row.iter().enumerate() {
-                            for x in field.split(&self.separator) {
-                                partials.push(ObservationPartial::Allele(i.to_string(), x.into()));
-                            }
+                            partials.extend(self.genotype(&i.to_string(), field));
                         }
                     }
                     self.observation_buffer = partials
                         .iter()
                         .map(|x| x.to_observation(&individual))
                         .collect();
+                    if idx == 0 {
+                        let loci = match &self.fields {
+                            Some(fields) => fields
+                                .iter()
+                                .filter_map(|field| match field {
+                                    Field::Locus(s) => Some(s.clone()),
+                                    _ => None,
+                                })
+                                .collect(),
+                            None => (0..row.len()).map(|i| i.to_string()).collect(),
+                        };
+                        for hint in self.hints(loci) {
+                            self.observation_buffer.push_front(hint);
+                        }
+                    }
                 }
-                Some((idx, Err(_))) => {
+                Some((_, Err(_))) => {
                     return None;
                 }
             }
@@ -129,6 +187,9 @@ pub struct CsvBuilder {
     headers: bool,
     delimiter: u8,
     separator: String,
+    missing_codes: HashSet<String>,
+    hint: Option<LocusHint>,
+    locus_hints: HashMap<String, LocusHint>,
     name_field: Option<String>,
     group_fields: HashSet<String>,
     group_field: Option<String>,
@@ -136,6 +197,12 @@ pub struct CsvBuilder {
     group_presence_identifier: String,
 }
 
+impl Default for CsvBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CsvBuilder {
     /// Construct a new Csv builder
     pub fn new() -> Self {
@@ -143,6 +210,9 @@ impl CsvBuilder {
             headers: true,
             delimiter: b',',
             separator: "/".to_owned(),
+            missing_codes: HashSet::new(),
+            hint: None,
+            locus_hints: HashMap::new(),
             name_field: None,
             group_fields: HashSet::new(),
             group_field: None,
@@ -166,6 +236,24 @@ impl CsvBuilder {
         self
     }
 
+    /// Allele codes marking a genotype as missing, none by default
+    pub fn missing_codes(&mut self, missing_codes: HashSet<String>) -> &mut Self {
+        self.missing_codes = missing_codes;
+        self
+    }
+
+    /// The kind of marker every locus holds, unless set by `locus_hints()`
+    pub fn hint(&mut self, hint: LocusHint) -> &mut Self {
+        self.hint = Some(hint);
+        self
+    }
+
+    /// The kind of marker each named locus holds
+    pub fn locus_hints(&mut self, locus_hints: HashMap<String, LocusHint>) -> &mut Self {
+        self.locus_hints = locus_hints;
+        self
+    }
+
     pub fn name_field(&mut self, name_field: &str) -> &mut Self {
         self.name_field = Some(name_field.to_owned());
         self
@@ -234,6 +322,9 @@ impl CsvBuilder {
             rdr.into_records(),
             fields,
             &self.separator,
+            &self.missing_codes,
+            self.hint,
+            &self.locus_hints,
             &self.group_presence_identifier,
         ))
     }
@@ -242,12 +333,11 @@ impl CsvBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::prelude::*;
 
     #[test]
     fn test_csv_with_header_has_correct_loci() -> Result<(), Box<dyn Error>> {
         let mut sample = Sample::new();
-        sample.observe(CsvBuilder::new().from_reader(Box::new("test\n0/0".as_bytes()))?);
+        sample.observe(CsvBuilder::new().from_reader(Box::new("test\n0/0".as_bytes()))?)?;
         assert_eq!(sample.loci_names(), vec!["test"]);
         Ok(())
     }
@@ -257,11 +347,28 @@ mod tests {
         let mut sample = Sample::new();
         sample.observe(
             CsvBuilder::new().from_reader(Box::new("test\n0/1/2/3/4\n5/6/7/8/9".as_bytes()))?,
-        );
+        )?;
         assert_eq!(
             sample.variations("test").unwrap(),
             (0..=9).map(|x| x.to_string()).collect::<Vec<String>>()
         );
         Ok(())
     }
+
+    #[test]
+    fn test_csv_hints_loci() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .hint(LocusHint::Snp)
+                .locus_hints(vec![("b".to_string(), LocusHint::Classical)].into_iter().collect())
+                .from_reader(Box::new("a,b,c
+A/G,x/y,C/C
+G/G,x/x,C/T".as_bytes()))?,
+        )?;
+        assert_eq!(sample.locus("a").unwrap().hint(), LocusHint::Snp);
+        assert_eq!(sample.locus("b").unwrap().hint(), LocusHint::Classical);
+        assert_eq!(sample.locus("c").unwrap().hint(), LocusHint::Snp);
+        Ok(())
+    }
 }
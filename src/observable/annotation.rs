@@ -0,0 +1,196 @@
+use crate::prelude::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Clone, Copy)]
+enum Column {
+    Locus,
+    Chromosome,
+    Position,
+    Reference,
+    RepeatLength,
+    Motif,
+    Hint,
+}
+
+impl Column {
+    fn parse(header: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(match header {
+            "locus" => Self::Locus,
+            "chromosome" => Self::Chromosome,
+            "position" => Self::Position,
+            "reference" => Self::Reference,
+            "repeat_length" => Self::RepeatLength,
+            "motif" => Self::Motif,
+            "hint" => Self::Hint,
+            _ => return Err(format!("unknown locus annotation column `{}`", header).into()),
+        })
+    }
+}
+
+/// Produces Observations from delimited locus annotation data
+///
+/// The header names a `locus` column and any of `chromosome`, `position`,
+/// `reference`, `repeat_length`, `motif` and `hint`, the last being one of
+/// `classical`, `microsatellite` or `snp`. Every row annotates the named
+/// `Locus`, and empty fields are skipped. Annotating a locus with no
+/// alleles adds it to the `Sample`, so annotations are best observed
+/// alongside genotypes. `LocusAnnotations` implements Iterator so it can
+/// be passed directly to `Sample::observe()`
+pub struct LocusAnnotations {
+    records: csv::StringRecordsIntoIter<Box<dyn Read>>,
+    columns: Vec<Column>,
+    observation_buffer: VecDeque<Observation>,
+}
+
+impl LocusAnnotations {
+    /// Reads one row into the observation buffer
+    fn read_row(&mut self, row: &csv::StringRecord) -> Result<(), Box<dyn Error>> {
+        let locus = self
+            .columns
+            .iter()
+            .zip(row.iter())
+            .find(|(column, _)| matches!(column, Column::Locus))
+            .map(|(_, field)| field.to_string())
+            .filter(|locus| !locus.is_empty())
+            .ok_or("locus annotation row has no locus")?;
+
+        for (column, field) in self.columns.iter().zip(row.iter()) {
+            if field.is_empty() {
+                continue;
+            }
+            let invalid = || format!("locus `{}` has an invalid annotation `{}`", locus, field);
+            let annotation = match column {
+                Column::Locus => continue,
+                Column::Chromosome => Annotation::Chromosome(field.to_string()),
+                Column::Position => Annotation::Position(field.parse().map_err(|_| invalid())?),
+                Column::Reference => Annotation::Reference(field.to_string()),
+                Column::RepeatLength => {
+                    Annotation::RepeatLength(field.parse().map_err(|_| invalid())?)
+                }
+                Column::Motif => Annotation::Motif(field.to_string()),
+                Column::Hint => Annotation::Hint(field.parse()?),
+            };
+            self.observation_buffer
+                .push_back(Observation::Annotation(locus.clone(), annotation));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for LocusAnnotations {
+    type Item = Result<Observation, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
+        while self.observation_buffer.is_empty() {
+            let row = match self.records.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if let Err(e) = self.read_row(&row) {
+                return Some(Err(e));
+            }
+        }
+        self.observation_buffer.pop_front().map(Ok)
+    }
+}
+
+pub struct LocusAnnotationsBuilder {
+    delimiter: u8,
+}
+
+impl Default for LocusAnnotationsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocusAnnotationsBuilder {
+    /// Construct a new comma delimited locus annotation builder
+    pub fn new() -> Self {
+        Self { delimiter: b',' }
+    }
+
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn from_reader(&self, reader: Box<dyn Read>) -> Result<LocusAnnotations, Box<dyn Error>> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .from_reader(reader);
+        let columns = rdr
+            .headers()?
+            .iter()
+            .map(Column::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if !columns.iter().any(|column| matches!(column, Column::Locus)) {
+            return Err("locus annotation data has no `locus` column".into());
+        }
+
+        Ok(LocusAnnotations {
+            records: rdr.into_records(),
+            columns,
+            observation_buffer: VecDeque::new(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(&self, path: P) -> Result<LocusAnnotations, Box<dyn Error>> {
+        self.from_reader(Box::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_locus_annotations_annotate_loci() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample
+            .observe(CsvBuilder::new().from_reader(Box::new("a,b\n200/204,10/12\n".as_bytes()))?)?;
+        sample.observe(
+            LocusAnnotationsBuilder::new().from_reader(Box::new(
+                "locus,chromosome,position,repeat_length,motif,hint\na,2,1500,,GATA,\nb,2,900,2,,SNP\n"
+                    .as_bytes(),
+            ))?,
+        )?;
+
+        let a = sample.locus("a").unwrap().annotation();
+        assert_eq!(a.chromosome(), Some("2"));
+        assert_eq!(a.position(), Some(1500));
+        assert_eq!(a.motif(), Some("GATA"));
+        assert_eq!(a.repeat_length(), Some(4));
+        assert_eq!(
+            sample.locus("b").unwrap().annotation().repeat_length(),
+            Some(2)
+        );
+        assert_eq!(a.hint(), LocusHint::Microsatellite);
+        assert_eq!(sample.locus("b").unwrap().hint(), LocusHint::Snp);
+        assert_eq!(sample.loci_names(), vec!["a", "b"]);
+
+        assert!(LocusAnnotationsBuilder::new()
+            .from_reader(Box::new("locus,colour\na,red\n".as_bytes()))
+            .is_err());
+
+        // Repeat lengths beyond `u32` are rejected rather than wrapped.
+        let result = sample.observe(
+            LocusAnnotationsBuilder::new()
+                .from_reader(Box::new("locus,repeat_length\na,4294967298\n".as_bytes()))?,
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("invalid annotation"));
+        assert_eq!(
+            sample.locus("a").unwrap().annotation().repeat_length(),
+            Some(4)
+        );
+        Ok(())
+    }
+}
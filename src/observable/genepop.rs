@@ -0,0 +1,292 @@
+use crate::prelude::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read, Write};
+use std::path::Path;
+
+/// Produces Observations from GenePop data
+///
+/// Each `Pop` section becomes a `Group` named `Pop1`, `Pop2`, and so on.
+/// Allele codes may have two or three digits and are observed without
+/// their leading zeros, so `012` becomes the `Variation` `12`. Codes of
+/// all zeros are observed as missing. `GenePop` implements Iterator so it
+/// can be passed directly to `Sample::observe()`
+pub struct GenePop {
+    lines: Lines<Box<dyn BufRead>>,
+    loci: Vec<String>,
+    population: usize,
+    observation_buffer: VecDeque<Observation>,
+}
+
+fn is_pop(line: &str) -> bool {
+    line.trim().eq_ignore_ascii_case("pop")
+}
+
+impl GenePop {
+    /// Reads one individual's line into the observation buffer
+    fn read_individual(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        let mut parts = line.splitn(2, ',');
+        let name = parts.next().unwrap_or_default().trim().to_string();
+        let genotypes: Vec<&str> = parts
+            .next()
+            .ok_or_else(|| format!("GenePop individual `{}` has no comma", name))?
+            .split_whitespace()
+            .collect();
+        if genotypes.len() != self.loci.len() {
+            return Err(format!(
+                "GenePop individual `{}` has {} genotypes but there are {} loci",
+                name,
+                genotypes.len(),
+                self.loci.len()
+            )
+            .into());
+        }
+
+        self.observation_buffer
+            .push_back(Observation::Group(name.clone(), format!("Pop{}", self.population)));
+        for (locus, genotype) in self.loci.iter().zip(genotypes) {
+            let digits = match genotype.len() {
+                2 | 4 => 2,
+                3 | 6 => 3,
+                _ => return Err(format!("GenePop genotype `{}` is not 2 or 3 digit coded", genotype).into()),
+            };
+            for code in genotype.as_bytes().chunks(digits) {
+                let code = std::str::from_utf8(code)?.parse::<u32>()?;
+                self.observation_buffer.push_back(if code == 0 {
+                    Observation::Missing(name.clone(), locus.clone())
+                } else {
+                    Observation::Allele(name.clone(), locus.clone(), code.to_string())
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for GenePop {
+    type Item = Result<Observation, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
+        while self.observation_buffer.is_empty() {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if is_pop(&line) {
+                self.population += 1;
+                continue;
+            }
+            if let Err(e) = self.read_individual(&line) {
+                return Some(Err(e));
+            }
+        }
+        self.observation_buffer.pop_front().map(Ok)
+    }
+}
+
+pub struct GenePopBuilder {}
+
+impl Default for GenePopBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GenePopBuilder {
+    /// Construct a new GenePop builder
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn from_reader(&self, reader: Box<dyn Read>) -> Result<GenePop, Box<dyn Error>> {
+        let reader: Box<dyn BufRead> = Box::new(BufReader::new(reader));
+        let mut lines = reader.lines();
+        lines.next().ok_or("GenePop data is empty")??;
+
+        // Loci are listed one per line or comma separated until the first Pop.
+        let mut loci = vec![];
+        let mut found_pop = false;
+        for line in lines.by_ref() {
+            let line = line?;
+            if is_pop(&line) {
+                found_pop = true;
+                break;
+            }
+            loci.extend(
+                line.split(',')
+                    .map(|locus| locus.trim().to_string())
+                    .filter(|locus| !locus.is_empty()),
+            );
+        }
+        if !found_pop {
+            return Err("GenePop data has no Pop".into());
+        }
+
+        Ok(GenePop {
+            lines,
+            loci,
+            population: 1,
+            observation_buffer: VecDeque::new(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(&self, path: P) -> Result<GenePop, Box<dyn Error>> {
+        self.from_reader(Box::new(File::open(path)?))
+    }
+}
+
+/// Serializes a `Sample` to GenePop
+///
+/// Individuals are written in the `Pop` of the first of their `Group`s by
+/// name, and individuals without a group in a final `Pop`. Loci whose
+/// variations are all numbers that fit in the allele code are written
+/// with those numbers, other loci have their variations numbered from 1.
+/// Individuals must carry at most two alleles at every locus.
+pub struct GenePopWriter {
+    title: String,
+    digits: usize,
+}
+
+impl Default for GenePopWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GenePopWriter {
+    /// Construct a new writer using 3 digit allele codes
+    pub fn new() -> Self {
+        Self {
+            title: "genomics".to_owned(),
+            digits: 3,
+        }
+    }
+
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = title.to_owned();
+        self
+    }
+
+    /// Use 2 or 3 digit allele codes
+    pub fn digits(&mut self, digits: usize) -> &mut Self {
+        self.digits = digits;
+        self
+    }
+
+    pub fn write<W: Write>(&self, sample: &Sample, mut writer: W) -> Result<(), Box<dyn Error>> {
+        if self.digits != 2 && self.digits != 3 {
+            return Err("GenePop allele codes have 2 or 3 digits".into());
+        }
+        let largest = 10u32.pow(self.digits as u32) - 1;
+
+        let codes: Vec<Vec<u32>> = sample
+            .loci
+            .values()
+            .map(|locus| {
+                let names: Vec<String> = locus.variations.lock().unwrap().keys().cloned().collect();
+                let numbers: Option<Vec<u32>> = names
+                    .iter()
+                    .map(|name| name.parse::<u32>().ok().filter(|n| *n > 0 && *n <= largest))
+                    .collect();
+                numbers.unwrap_or_else(|| (1..=names.len() as u32).collect())
+            })
+            .collect();
+        if codes.iter().any(|codes| codes.iter().any(|code| *code > largest)) {
+            return Err("a locus has more variations than fit in the allele code".into());
+        }
+
+        writeln!(writer, "{}", self.title)?;
+        for locus in sample.loci.keys() {
+            writeln!(writer, "{}", locus)?;
+        }
+
+        let mut populations: Vec<(Option<String>, Vec<&Individual>)> = sample
+            .group_names()
+            .into_iter()
+            .map(|group| (Some(group.clone()), vec![]))
+            .collect();
+        populations.push((None, vec![]));
+        for individual in sample.individuals.values() {
+            let first = individual.groups.iter().map(|group| &group.name).min();
+            let population = populations
+                .iter_mut()
+                .find(|(group, _)| group.as_ref() == first)
+                .unwrap(); // Every group of the sample has a population.
+            population.1.push(individual);
+        }
+
+        for (_, individuals) in populations.iter().filter(|(_, i)| !i.is_empty()) {
+            writeln!(writer, "Pop")?;
+            for individual in individuals {
+                write!(writer, "{} ,", individual.name)?;
+                for (locus, codes) in sample.loci.values().zip(codes.iter()) {
+                    let alleles: Vec<u32> = individual
+                        .locus_counts(locus)
+                        .iter()
+                        .zip(codes.iter())
+                        .flat_map(|(count, code)| std::iter::repeat_n(*code, *count as usize))
+                        .collect();
+                    let alleles = match alleles.len() {
+                        0 => vec![0, 0],
+                        1 | 2 => alleles,
+                        n => {
+                            return Err(format!(
+                                "individual `{}` has {} alleles at locus `{}`, GenePop allows 2",
+                                individual.name, n, locus.name
+                            )
+                            .into())
+                        }
+                    };
+                    write!(writer, " ")?;
+                    for allele in alleles {
+                        write!(writer, "{:0width$}", allele, width = self.digits)?;
+                    }
+                }
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENEPOP: &str = "Example data\nLoc1, Loc2\nLoc3\nPop\nA1 , 0102 0303 000000\nA2 , 0202 0103 0000\npop\nB1 , 0101 0000 0405\n";
+
+    #[test]
+    fn test_genepop_has_correct_loci_and_groups() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(GenePopBuilder::new().from_reader(Box::new(GENEPOP.as_bytes()))?)?;
+        assert_eq!(sample.loci_names(), vec!["Loc1", "Loc2", "Loc3"]);
+        assert_eq!(sample.variations("Loc1").unwrap(), vec!["1", "2"]);
+        assert_eq!(sample.variations("Loc3").unwrap(), vec!["4", "5"]);
+        assert_eq!(sample.group_names(), vec!["Pop1", "Pop2"]);
+        assert!(sample.individuals["B1"].in_group("Pop2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_genepop_round_trip() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(GenePopBuilder::new().from_reader(Box::new(GENEPOP.as_bytes()))?)?;
+
+        let mut written = vec![];
+        GenePopWriter::new().title("Example data").digits(2).write(&sample, &mut written)?;
+        assert_eq!(
+            String::from_utf8(written.clone())?,
+            "Example data\nLoc1\nLoc2\nLoc3\nPop\nA1 , 0102 0303 0000\nA2 , 0202 0103 0000\nPop\nB1 , 0101 0000 0405\n"
+        );
+
+        let mut round_trip = Sample::new();
+        round_trip.observe(GenePopBuilder::new().from_reader(Box::new(std::io::Cursor::new(written)))?)?;
+        assert_eq!(round_trip.loci_names(), sample.loci_names());
+        assert_eq!(round_trip.individuals.len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,577 @@
+use crate::prelude::*;
+use crate::{AlleleCount, AlleleMatrix};
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 3] = [0x6c, 0x1b, 0x01];
+
+/// A row of a `.fam` file
+struct Family {
+    /// The name of the `Individual`
+    name: String,
+    family: String,
+    paternal: String,
+    maternal: String,
+    sex: String,
+    phenotype: String,
+}
+
+/// A row of a `.bim` file
+struct Variant {
+    locus: String,
+    chromosome: String,
+    position: String,
+    allele_1: String,
+    allele_2: String,
+}
+
+fn read_rows(
+    reader: Box<dyn Read>,
+    columns: usize,
+    kind: &str,
+) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    let mut rows = vec![];
+    for line in BufReader::new(reader).lines() {
+        let row: Vec<String> = line?.split_whitespace().map(|s| s.to_string()).collect();
+        if row.is_empty() {
+            continue;
+        }
+        if row.len() < columns {
+            return Err(format!(
+                "PLINK {} row has {} columns, expected {}",
+                kind,
+                row.len(),
+                columns
+            )
+            .into());
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// The number of copies of allele 1 and allele 2 of the `i`th individual
+/// in the `.bed` block of a variant, or `None` when missing
+fn genotype(block: &[u8], i: usize) -> Option<(AlleleCount, AlleleCount)> {
+    match (block[i / 4] >> ((i % 4) * 2)) & 0b11 {
+        0b00 => Some((2, 0)),
+        0b10 => Some((1, 1)),
+        0b11 => Some((0, 2)),
+        _ => None,
+    }
+}
+
+/// Produces Observations from PLINK binary `.bed`, `.bim` and `.fam` data
+///
+/// Individuals are named by their individual ID, or `family:individual`
+/// with `PlinkBuilder::family_names()`, and observed in a `Group` named by
+/// their family ID. Parental IDs other than `0`, sex and phenotype
+/// are observed as `Meta` named `paternal_id`, `maternal_id`, `sex` and
+/// `phenotype`. Loci are named by their variant ID, or `chromosome:position`
+/// when the ID is `.`, annotated with their chromosome and position unless
+/// those are `0`, and alleles by the allele codes of the `.bim`. Allele 2
+/// is annotated as the reference allele, as PLINK reads it from VCF data,
+/// so that `PlinkWriter` keeps the allele order. Loci whose allele codes
+/// are single characters hold a `Snp`, other loci are `Classical`.
+/// Missing genotypes are observed as missing. `Plink` implements Iterator
+/// so it can be passed directly to `Sample::observe()`; genome-scale panels
+/// are better read straight into an `AlleleMatrix` with
+/// `PlinkBuilder::matrix_from_readers()`.
+pub struct Plink {
+    bed: Box<dyn Read>,
+    families: Vec<Family>,
+    variants: std::vec::IntoIter<Variant>,
+    observation_buffer: VecDeque<Observation>,
+}
+
+impl Plink {
+    /// Reads the genotypes of one variant into the observation buffer
+    fn read_variant(&mut self, variant: Variant) -> Result<(), Box<dyn Error>> {
+        // Chromosome and position `0` mean unknown.
+        if variant.chromosome != "0" {
+            self.observation_buffer.push_back(Observation::Annotation(
+                variant.locus.clone(),
+                Annotation::Chromosome(variant.chromosome.clone()),
+            ));
+        }
+        let hint = if variant.allele_1.len() == 1 && variant.allele_2.len() == 1 {
+            LocusHint::Snp
+        } else {
+            LocusHint::Classical
+        };
+        self.observation_buffer.push_back(Observation::Annotation(
+            variant.locus.clone(),
+            Annotation::Hint(hint),
+        ));
+        if variant.allele_2 != "0" {
+            self.observation_buffer.push_back(Observation::Annotation(
+                variant.locus.clone(),
+                Annotation::Reference(variant.allele_2.clone()),
+            ));
+        }
+        let position = variant.position.parse::<u64>().map_err(|_| {
+            format!(
+                "PLINK variant `{}` has an invalid position `{}`",
+                variant.locus, variant.position
+            )
+        })?;
+        if position != 0 {
+            self.observation_buffer.push_back(Observation::Annotation(
+                variant.locus.clone(),
+                Annotation::Position(position),
+            ));
+        }
+        let mut block = vec![0u8; self.families.len().div_ceil(4)];
+        self.bed.read_exact(&mut block)?;
+        for (i, family) in self.families.iter().enumerate() {
+            let alleles: &[&String] = match genotype(&block, i) {
+                Some((2, 0)) => &[&variant.allele_1, &variant.allele_1],
+                Some((1, 1)) => &[&variant.allele_1, &variant.allele_2],
+                Some(_) => &[&variant.allele_2, &variant.allele_2],
+                None => {
+                    self.observation_buffer.push_back(Observation::Missing(
+                        family.name.clone(),
+                        variant.locus.clone(),
+                    ));
+                    continue;
+                }
+            };
+            for allele in alleles {
+                self.observation_buffer.push_back(Observation::Allele(
+                    family.name.clone(),
+                    variant.locus.clone(),
+                    allele.to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Plink {
+    type Item = Result<Observation, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
+        while self.observation_buffer.is_empty() {
+            let variant = self.variants.next()?;
+            if let Err(e) = self.read_variant(variant) {
+                return Some(Err(e));
+            }
+        }
+        self.observation_buffer.pop_front().map(Ok)
+    }
+}
+
+pub struct PlinkBuilder {
+    family_names: bool,
+}
+
+impl Default for PlinkBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlinkBuilder {
+    /// Construct a new Plink builder
+    pub fn new() -> Self {
+        Self {
+            family_names: false,
+        }
+    }
+
+    /// Whether to name individuals `family:individual`, as needed when
+    /// individual IDs repeat across families
+    ///
+    /// Otherwise repeated individual IDs are an error.
+    pub fn family_names(&mut self, family_names: bool) -> &mut Self {
+        self.family_names = family_names;
+        self
+    }
+
+    /// Checks the `.bed` header and reads the `.fam` and `.bim` rows
+    fn read_headers(
+        &self,
+        bed: &mut Box<dyn Read>,
+        bim: Box<dyn Read>,
+        fam: Box<dyn Read>,
+    ) -> Result<(Vec<Family>, Vec<Variant>), Box<dyn Error>> {
+        let mut magic = [0u8; 3];
+        bed.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err("not a SNP-major PLINK .bed file".into());
+        }
+
+        let families: Vec<Family> = read_rows(fam, 6, ".fam")?
+            .into_iter()
+            .map(|row| Family {
+                name: if self.family_names {
+                    format!("{}:{}", row[0], row[1])
+                } else {
+                    row[1].clone()
+                },
+                family: row[0].clone(),
+                paternal: row[2].clone(),
+                maternal: row[3].clone(),
+                sex: row[4].clone(),
+                phenotype: row[5].clone(),
+            })
+            .collect();
+        let mut names = HashSet::new();
+        if let Some(family) = families.iter().find(|family| !names.insert(&family.name)) {
+            return Err(format!(
+                "PLINK individual `{}` appears more than once, name individuals by family too",
+                family.name
+            )
+            .into());
+        }
+        let variants: Vec<Variant> = read_rows(bim, 6, ".bim")?
+            .into_iter()
+            .map(|row| Variant {
+                locus: if row[1] == "." {
+                    format!("{}:{}", row[0], row[3])
+                } else {
+                    row[1].clone()
+                },
+                chromosome: row[0].clone(),
+                position: row[3].clone(),
+                allele_1: row[4].clone(),
+                allele_2: row[5].clone(),
+            })
+            .collect();
+        Ok((families, variants))
+    }
+
+    pub fn from_readers(
+        &self,
+        mut bed: Box<dyn Read>,
+        bim: Box<dyn Read>,
+        fam: Box<dyn Read>,
+    ) -> Result<Plink, Box<dyn Error>> {
+        let (families, variants) = self.read_headers(&mut bed, bim, fam)?;
+
+        let mut observation_buffer = VecDeque::new();
+        for family in families.iter() {
+            let name = &family.name;
+            observation_buffer.push_back(Observation::Group(name.clone(), family.family.clone()));
+            for (meta, content) in [
+                ("paternal_id", &family.paternal),
+                ("maternal_id", &family.maternal),
+            ] {
+                if content != "0" {
+                    observation_buffer.push_back(Observation::Meta(
+                        name.clone(),
+                        meta.to_owned(),
+                        content.clone(),
+                    ));
+                }
+            }
+            observation_buffer.push_back(Observation::Meta(
+                name.clone(),
+                "sex".to_owned(),
+                family.sex.clone(),
+            ));
+            observation_buffer.push_back(Observation::Meta(
+                name.clone(),
+                "phenotype".to_owned(),
+                family.phenotype.clone(),
+            ));
+        }
+
+        Ok(Plink {
+            bed,
+            families,
+            variants: variants.into_iter(),
+            observation_buffer,
+        })
+    }
+
+    /// Reads `prefix.bed`, `prefix.bim` and `prefix.fam`
+    pub fn from_prefix<P: AsRef<Path>>(&self, prefix: P) -> Result<Plink, Box<dyn Error>> {
+        let (bed, bim, fam) = open_prefix(prefix)?;
+        self.from_readers(bed, bim, fam)
+    }
+
+    /// Reads the genotypes straight into an `AlleleMatrix`, without the
+    /// cost of observing every allele into a `Sample`
+    ///
+    /// Rows are the individuals in `.fam` order, named as by
+    /// `from_readers()`. Every variant takes two columns, for allele 1
+    /// and allele 2, in `.bim` order. Missing genotypes have no alleles.
+    /// An `AlleleMatrix` holds no groups, so the family ID of every row
+    /// is returned alongside it.
+    pub fn matrix_from_readers(
+        &self,
+        mut bed: Box<dyn Read>,
+        bim: Box<dyn Read>,
+        fam: Box<dyn Read>,
+    ) -> Result<(AlleleMatrix, Vec<String>), Box<dyn Error>> {
+        let (families, variants) = self.read_headers(&mut bed, bim, fam)?;
+        let n = families.len();
+        let mut data = ndarray::Array2::<AlleleCount>::zeros((n, 2 * variants.len()));
+        let mut block = vec![0u8; n.div_ceil(4)];
+        for v in 0..variants.len() {
+            bed.read_exact(&mut block)?;
+            for i in 0..n {
+                if let Some((first, second)) = genotype(&block, i) {
+                    data[[i, 2 * v]] = first;
+                    data[[i, 2 * v + 1]] = second;
+                }
+            }
+        }
+        let mut matrix = AlleleMatrix::new();
+        matrix.data = data;
+        matrix.loci = (0..variants.len()).map(|v| (2 * v, 2 * v + 2)).collect();
+        let (individual_names, groups) = families
+            .into_iter()
+            .map(|family| (family.name, family.family))
+            .unzip();
+        matrix.individual_names = individual_names;
+        matrix.variation_names = variants
+            .iter()
+            .flat_map(|variant| vec![variant.allele_1.clone(), variant.allele_2.clone()])
+            .collect();
+        matrix.repeat_lengths = vec![None; variants.len()];
+        matrix.allele_sizes = vec![None; 2 * variants.len()];
+        Ok((matrix, groups))
+    }
+
+    /// Reads `prefix.bed`, `prefix.bim` and `prefix.fam` straight into an
+    /// `AlleleMatrix`, along with the family ID of every row
+    pub fn matrix_from_prefix<P: AsRef<Path>>(
+        &self,
+        prefix: P,
+    ) -> Result<(AlleleMatrix, Vec<String>), Box<dyn Error>> {
+        let (bed, bim, fam) = open_prefix(prefix)?;
+        self.matrix_from_readers(bed, bim, fam)
+    }
+}
+
+type Readers = (Box<dyn Read>, Box<dyn Read>, Box<dyn Read>);
+
+/// Opens `prefix.bed`, `prefix.bim` and `prefix.fam`
+fn open_prefix<P: AsRef<Path>>(prefix: P) -> Result<Readers, Box<dyn Error>> {
+    let prefix = prefix.as_ref().to_string_lossy().into_owned();
+    Ok((
+        Box::new(BufReader::new(File::open(format!("{}.bed", prefix))?)),
+        Box::new(File::open(format!("{}.bim", prefix))?),
+        Box::new(File::open(format!("{}.fam", prefix))?),
+    ))
+}
+
+/// Serializes a `Sample` to PLINK binary `.bed`, `.bim` and `.fam` data
+///
+/// Every locus must have at most two variations and every individual
+/// zero or two alleles at each locus. A variation annotated as the
+/// reference allele is written as allele 2, as read by `PlinkBuilder`,
+/// otherwise the first variation of a locus by name is allele 1. Family IDs are the first of each
+/// individual's `Group`s by name, or the individual's name when it has
+/// none, and individual IDs drop a leading `family:` as added by
+/// `PlinkBuilder::family_names()`. The `Meta` read by `PlinkBuilder` is
+/// written back. Loci
+/// without an annotated chromosome or position are written on chromosome
+/// `0` or at position `0`.
+pub struct PlinkWriter {}
+
+impl Default for PlinkWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlinkWriter {
+    /// Construct a new Plink writer
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn write<B: Write, M: Write, F: Write>(
+        &self,
+        sample: &Sample,
+        mut bed: B,
+        mut bim: M,
+        mut fam: F,
+    ) -> Result<(), Box<dyn Error>> {
+        for individual in sample.individuals.values() {
+            let family = individual
+                .groups
+                .iter()
+                .map(|group| &group.name)
+                .min()
+                .unwrap_or(&individual.name);
+            let name = individual
+                .name
+                .strip_prefix(&format!("{}:", family))
+                .unwrap_or(&individual.name);
+            let meta = |key: &str, default: &'static str| {
+                individual
+                    .meta
+                    .get(key)
+                    .map_or(default, |value| value.as_str())
+            };
+            writeln!(
+                fam,
+                "{} {} {} {} {} {}",
+                family,
+                name,
+                meta("paternal_id", "0"),
+                meta("maternal_id", "0"),
+                meta("sex", "0"),
+                meta("phenotype", "-9")
+            )?;
+        }
+
+        bed.write_all(&MAGIC)?;
+        for locus in sample.loci.values() {
+            let variations: Vec<String> =
+                locus.variations.lock().unwrap().keys().cloned().collect();
+            if variations.len() > 2 {
+                return Err(format!("locus `{}` has more than two variations", locus.name).into());
+            }
+            let annotation = locus.annotation();
+            // The indices of the variations written as allele 1 and allele 2
+            let reference = annotation
+                .reference()
+                .and_then(|reference| variations.iter().position(|v| v == reference));
+            let alleles = match reference {
+                Some(r) => [(0..variations.len()).find(|v| *v != r), Some(r)],
+                None => [
+                    (!variations.is_empty()).then_some(0),
+                    (variations.len() > 1).then_some(1),
+                ],
+            };
+            let allele = |a: usize| alleles[a].map_or("0", |v| variations[v].as_str());
+            writeln!(
+                bim,
+                "{}\t{}\t0\t{}\t{}\t{}",
+                annotation.chromosome().unwrap_or("0"),
+                locus.name,
+                annotation.position().unwrap_or(0),
+                allele(0),
+                allele(1)
+            )?;
+
+            let mut block = vec![0u8; sample.individuals.len().div_ceil(4)];
+            for (i, individual) in sample.individuals.values().enumerate() {
+                let counts = individual.locus_counts(locus);
+                let count = |a: usize| alleles[a].and_then(|v| counts.get(v).copied()).unwrap_or(0);
+                let code = match (count(0), count(1)) {
+                    (0, 0) => 0b01,
+                    (2, 0) => 0b00,
+                    (1, 1) => 0b10,
+                    (0, 2) => 0b11,
+                    _ => {
+                        return Err(format!(
+                            "individual `{}` is not diploid at locus `{}`",
+                            individual.name, locus.name
+                        )
+                        .into())
+                    }
+                };
+                block[i / 4] |= code << ((i % 4) * 2);
+            }
+            bed.write_all(&block)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `prefix.bed`, `prefix.bim` and `prefix.fam`
+    pub fn write_prefix<P: AsRef<Path>>(
+        &self,
+        sample: &Sample,
+        prefix: P,
+    ) -> Result<(), Box<dyn Error>> {
+        let prefix = prefix.as_ref().to_string_lossy().into_owned();
+        let mut bed = BufWriter::new(File::create(format!("{}.bed", prefix))?);
+        let mut bim = BufWriter::new(File::create(format!("{}.bim", prefix))?);
+        let mut fam = BufWriter::new(File::create(format!("{}.fam", prefix))?);
+        self.write(sample, &mut bed, &mut bim, &mut fam)?;
+        bed.flush()?;
+        bim.flush()?;
+        fam.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_plink_reads_genotypes_and_families() -> Result<(), Box<dyn Error>> {
+        // Individuals a, b, c, d, e at rs1 are A/A, A/G, missing, G/G, A/A.
+        let bed = vec![0x6c, 0x1b, 0x01, 0b1101_1000, 0b0000_0000];
+        let bim = "1\trs1\t0\t100\tA\tG\n";
+        let fam = "f1 a 0 0 1 -9\nf1 b 0 0 2 -9\nf2 c a b 1 2\nf2 d 0 0 0 1\nf3 e 0 0 1 1\n";
+
+        let mut sample = Sample::new();
+        sample.observe(PlinkBuilder::new().from_readers(
+            Box::new(Cursor::new(bed)),
+            Box::new(bim.as_bytes()),
+            Box::new(fam.as_bytes()),
+        )?)?;
+        assert_eq!(sample.loci_names(), vec!["rs1"]);
+        assert_eq!(sample.variations("rs1").unwrap(), vec!["A", "G"]);
+        assert_eq!(sample.locus("rs1").unwrap().hint(), LocusHint::Snp);
+        assert_eq!(sample.group_names(), vec!["f1", "f2", "f3"]);
+        let rs1 = sample.loci["rs1"].clone();
+        let counts: Vec<Vec<u32>> = sample
+            .individuals
+            .values()
+            .map(|individual| individual.locus_counts(&rs1))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![vec![2, 0], vec![1, 1], vec![0, 0], vec![0, 2], vec![2, 0]]
+        );
+        assert_eq!(sample.individuals["c"].meta["paternal_id"], "a");
+
+        let (mut bed_out, mut bim_out, mut fam_out) = (vec![], vec![], vec![]);
+        PlinkWriter::new().write(&sample, &mut bed_out, &mut bim_out, &mut fam_out)?;
+        assert_eq!(bed_out, vec![0x6c, 0x1b, 0x01, 0b1101_1000, 0b0000_0000]);
+        assert_eq!(String::from_utf8(bim_out)?, bim);
+        assert_eq!(String::from_utf8(fam_out)?, fam);
+
+        let (matrix, groups) = PlinkBuilder::new().matrix_from_readers(
+            Box::new(Cursor::new(bed_out)),
+            Box::new(bim.as_bytes()),
+            Box::new(fam.as_bytes()),
+        )?;
+        sample.flush()?;
+        assert_eq!(matrix.data(), sample.matrix.data());
+        assert_eq!(matrix.individual_names(), sample.matrix.individual_names());
+        assert_eq!(matrix.variation_names(), ["A", "G"]);
+        assert_eq!(groups, ["f1", "f1", "f2", "f2", "f3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plink_individual_ids_repeated_across_families() -> Result<(), Box<dyn Error>> {
+        // Allele 1 sorts after allele 2, and keeps its place when written.
+        let bed = vec![0x6c, 0x1b, 0x01, 0b0000_1000];
+        let bim = "1\trs1\t0\t100\tT\tC\n";
+        let fam = "f1 a 0 0 1 -9\nf2 a 0 0 2 -9\n";
+        let read = |builder: &PlinkBuilder| {
+            builder.from_readers(
+                Box::new(Cursor::new(bed.clone())),
+                Box::new(bim.as_bytes()),
+                Box::new(fam.as_bytes()),
+            )
+        };
+        assert!(read(&PlinkBuilder::new()).is_err());
+
+        let mut sample = Sample::new();
+        sample.observe(read(PlinkBuilder::new().family_names(true))?)?;
+        assert_eq!(sample.individuals.keys().collect::<Vec<_>>(), ["f1:a", "f2:a"]);
+        let (mut bed_out, mut bim_out, mut fam_out) = (vec![], vec![], vec![]);
+        PlinkWriter::new().write(&sample, &mut bed_out, &mut bim_out, &mut fam_out)?;
+        assert_eq!(bed_out, bed);
+        assert_eq!(String::from_utf8(bim_out)?, bim);
+        assert_eq!(String::from_utf8(fam_out)?, fam);
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+use crate::prelude::*;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read, Write};
+use std::path::Path;
+
+/// Produces Observations from STRUCTURE input data
+///
+/// Individuals take one row per allele copy (`ploidy` rows) unless the
+/// data has one row per individual, in which case each locus takes
+/// `ploidy` columns. A `PopData` column is observed as a `Group`, and
+/// `PopFlag` and `LocData` columns as `Meta` of the same names, and
+/// genotypes with a missing allele are observed as missing. Without marker
+/// names, loci are named by their zero padded column index, such as `00`
+/// to `10`. `Structure` implements Iterator so it can be passed directly
+/// to `Sample::observe()`
+pub struct Structure {
+    lines: Lines<Box<dyn BufRead>>,
+    loci: Option<Vec<String>>,
+    options: StructureBuilder,
+    individuals: usize,
+    observation_buffer: VecDeque<Observation>,
+}
+
+impl Structure {
+    /// Reads one individual's rows into the observation buffer
+    fn read_individual(&mut self, rows: &[Vec<String>]) -> Result<(), Box<dyn Error>> {
+        let options = &self.options;
+        let leading = options.leading_columns();
+        let name = if options.label {
+            rows[0][0].clone()
+        } else {
+            self.individuals.to_string()
+        };
+        self.individuals += 1;
+
+        let mut column = if options.label { 1 } else { 0 };
+        if options.pop_data {
+            self.observation_buffer
+                .push_back(Observation::Group(name.clone(), rows[0][column].clone()));
+            column += 1;
+        }
+        if options.pop_flag {
+            self.observation_buffer.push_back(Observation::Meta(
+                name.clone(),
+                "PopFlag".to_owned(),
+                rows[0][column].clone(),
+            ));
+            column += 1;
+        }
+        if options.loc_data {
+            self.observation_buffer.push_back(Observation::Meta(
+                name.clone(),
+                "LocData".to_owned(),
+                rows[0][column].clone(),
+            ));
+        }
+
+        // Collect every allele of every locus, whichever the layout.
+        let copies = if options.one_row_per_individual {
+            options.ploidy
+        } else {
+            1
+        };
+        if !(rows[0].len() - leading).is_multiple_of(copies) {
+            return Err(format!(
+                "STRUCTURE individual `{}` has {} allele columns, not a multiple of its ploidy {}",
+                name,
+                rows[0].len() - leading,
+                copies
+            )
+            .into());
+        }
+        let n_loci = (rows[0].len() - leading) / copies;
+        if let Some(loci) = &self.loci {
+            if loci.len() != n_loci {
+                return Err(format!(
+                    "STRUCTURE individual `{}` has {} loci but there are {} marker names",
+                    name,
+                    n_loci,
+                    loci.len()
+                )
+                .into());
+            }
+        }
+        // Unnamed loci are numbered with zero padding so they sort in column order.
+        let width = n_loci.saturating_sub(1).to_string().len();
+        for row in rows.iter() {
+            if row.len() != rows[0].len() {
+                return Err(
+                    format!("STRUCTURE rows of individual `{}` differ in length", name).into(),
+                );
+            }
+            for (i, allele) in row[leading..].iter().enumerate() {
+                let locus = match &self.loci {
+                    Some(loci) => loci[i / copies].clone(),
+                    None => format!("{:0width$}", i / copies, width = width),
+                };
+                self.observation_buffer.push_back(if *allele == options.missing {
+                    Observation::Missing(name.clone(), locus)
+                } else {
+                    Observation::Allele(name.clone(), locus, allele.clone())
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Structure {
+    type Item = Result<Observation, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
+        let rows_per_individual = if self.options.one_row_per_individual {
+            1
+        } else {
+            self.options.ploidy
+        };
+        while self.observation_buffer.is_empty() {
+            let mut rows = vec![];
+            while rows.len() < rows_per_individual {
+                match self.lines.next() {
+                    None if rows.is_empty() => return None,
+                    None => {
+                        return Some(Err(
+                            "STRUCTURE data ends part way through an individual".into()
+                        ))
+                    }
+                    Some(Err(e)) => return Some(Err(e.into())),
+                    Some(Ok(line)) => {
+                        let row: Vec<String> =
+                            line.split_whitespace().map(|s| s.to_string()).collect();
+                        if !row.is_empty() {
+                            rows.push(row);
+                        }
+                    }
+                }
+            }
+            if rows[0].len() < self.options.leading_columns() {
+                return Some(Err(
+                    "STRUCTURE row is shorter than its leading columns".into()
+                ));
+            }
+            if let Err(e) = self.read_individual(&rows) {
+                return Some(Err(e));
+            }
+        }
+        self.observation_buffer.pop_front().map(Ok)
+    }
+}
+
+#[derive(Clone)]
+pub struct StructureBuilder {
+    marker_names: bool,
+    label: bool,
+    pop_data: bool,
+    pop_flag: bool,
+    loc_data: bool,
+    extra_columns: usize,
+    one_row_per_individual: bool,
+    ploidy: usize,
+    missing: String,
+}
+
+impl Default for StructureBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StructureBuilder {
+    /// Construct a new builder for diploid data with marker names, labels,
+    /// two rows per individual and `-9` for missing data
+    pub fn new() -> Self {
+        Self {
+            marker_names: true,
+            label: true,
+            pop_data: false,
+            pop_flag: false,
+            loc_data: false,
+            extra_columns: 0,
+            one_row_per_individual: false,
+            ploidy: 2,
+            missing: "-9".to_owned(),
+        }
+    }
+
+    /// The first row holds the name of every locus
+    pub fn marker_names(&mut self, marker_names: bool) -> &mut Self {
+        self.marker_names = marker_names;
+        self
+    }
+
+    /// The first column holds the name of each individual
+    pub fn label(&mut self, label: bool) -> &mut Self {
+        self.label = label;
+        self
+    }
+
+    pub fn pop_data(&mut self, pop_data: bool) -> &mut Self {
+        self.pop_data = pop_data;
+        self
+    }
+
+    pub fn pop_flag(&mut self, pop_flag: bool) -> &mut Self {
+        self.pop_flag = pop_flag;
+        self
+    }
+
+    pub fn loc_data(&mut self, loc_data: bool) -> &mut Self {
+        self.loc_data = loc_data;
+        self
+    }
+
+    /// The number of phenotype or extra columns before the genotypes, which are ignored
+    pub fn extra_columns(&mut self, extra_columns: usize) -> &mut Self {
+        self.extra_columns = extra_columns;
+        self
+    }
+
+    pub fn one_row_per_individual(&mut self, one_row_per_individual: bool) -> &mut Self {
+        self.one_row_per_individual = one_row_per_individual;
+        self
+    }
+
+    pub fn ploidy(&mut self, ploidy: usize) -> &mut Self {
+        self.ploidy = ploidy;
+        self
+    }
+
+    /// The code of a missing allele
+    pub fn missing(&mut self, missing: &str) -> &mut Self {
+        self.missing = missing.to_owned();
+        self
+    }
+
+    fn leading_columns(&self) -> usize {
+        [self.label, self.pop_data, self.pop_flag, self.loc_data]
+            .iter()
+            .filter(|column| **column)
+            .count()
+            + self.extra_columns
+    }
+
+    pub fn from_reader(&self, reader: Box<dyn Read>) -> Result<Structure, Box<dyn Error>> {
+        if self.ploidy == 0 {
+            return Err("STRUCTURE ploidy must be at least 1".into());
+        }
+        let reader: Box<dyn BufRead> = Box::new(BufReader::new(reader));
+        let mut lines = reader.lines();
+        let loci = if self.marker_names {
+            let mut names = vec![];
+            for line in lines.by_ref() {
+                names = line?.split_whitespace().map(|s| s.to_string()).collect();
+                if !names.is_empty() {
+                    break;
+                }
+            }
+            Some(names)
+        } else {
+            None
+        };
+
+        Ok(Structure {
+            lines,
+            loci,
+            options: self.clone(),
+            individuals: 0,
+            observation_buffer: VecDeque::new(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(&self, path: P) -> Result<Structure, Box<dyn Error>> {
+        self.from_reader(Box::new(File::open(path)?))
+    }
+}
+
+/// Serializes a `Sample` to STRUCTURE input
+///
+/// Every individual is labelled. With `pop_data` the first of each
+/// individual's `Group`s by name is written as its position among the
+/// sorted group names, starting at 1, and individuals without a group
+/// are written as 0. Loci whose variations are all integers are written
+/// with those integers, other loci have their variations numbered from 1.
+/// Individuals with fewer alleles than the ploidy are padded with the
+/// missing code.
+pub struct StructureWriter {
+    marker_names: bool,
+    pop_data: bool,
+    one_row_per_individual: bool,
+    ploidy: usize,
+    missing: String,
+}
+
+impl Default for StructureWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StructureWriter {
+    /// Construct a new writer with the same defaults as `StructureBuilder`
+    pub fn new() -> Self {
+        Self {
+            marker_names: true,
+            pop_data: false,
+            one_row_per_individual: false,
+            ploidy: 2,
+            missing: "-9".to_owned(),
+        }
+    }
+
+    pub fn marker_names(&mut self, marker_names: bool) -> &mut Self {
+        self.marker_names = marker_names;
+        self
+    }
+
+    pub fn pop_data(&mut self, pop_data: bool) -> &mut Self {
+        self.pop_data = pop_data;
+        self
+    }
+
+    pub fn one_row_per_individual(&mut self, one_row_per_individual: bool) -> &mut Self {
+        self.one_row_per_individual = one_row_per_individual;
+        self
+    }
+
+    pub fn ploidy(&mut self, ploidy: usize) -> &mut Self {
+        self.ploidy = ploidy;
+        self
+    }
+
+    pub fn missing(&mut self, missing: &str) -> &mut Self {
+        self.missing = missing.to_owned();
+        self
+    }
+
+    pub fn write<W: Write>(&self, sample: &Sample, mut writer: W) -> Result<(), Box<dyn Error>> {
+        let codes: Vec<Vec<String>> = sample
+            .loci
+            .values()
+            .map(|locus| {
+                let names: Vec<String> = locus.variations.lock().unwrap().keys().cloned().collect();
+                if names.iter().all(|name| name.parse::<i64>().is_ok()) {
+                    names
+                } else {
+                    (1..=names.len()).map(|code| code.to_string()).collect()
+                }
+            })
+            .collect();
+        let groups = sample.group_names();
+
+        if self.marker_names {
+            let names: Vec<&str> = sample.loci.keys().map(|name| name.as_str()).collect();
+            writeln!(writer, "{}", names.join(" "))?;
+        }
+
+        for individual in sample.individuals.values() {
+            let mut leading = vec![individual.name.clone()];
+            if self.pop_data {
+                let first = individual.groups.iter().map(|group| &group.name).min();
+                let pop = first.map_or(0, |first| {
+                    groups.iter().position(|g| *g == first).unwrap() + 1
+                });
+                leading.push(pop.to_string());
+            }
+
+            // One row of alleles per copy, each with one allele per locus.
+            let mut rows: Vec<Vec<String>> = vec![vec![]; self.ploidy];
+            for (locus, codes) in sample.loci.values().zip(codes.iter()) {
+                let mut alleles: Vec<String> = individual
+                    .locus_counts(locus)
+                    .iter()
+                    .zip(codes.iter())
+                    .flat_map(|(count, code)| std::iter::repeat_n(code.clone(), *count as usize))
+                    .collect();
+                if alleles.len() > self.ploidy {
+                    return Err(format!(
+                        "individual `{}` has {} alleles at locus `{}` but the ploidy is {}",
+                        individual.name,
+                        alleles.len(),
+                        locus.name,
+                        self.ploidy
+                    )
+                    .into());
+                }
+                alleles.resize(self.ploidy, self.missing.clone());
+                for (row, allele) in rows.iter_mut().zip(alleles) {
+                    row.push(allele);
+                }
+            }
+
+            if self.one_row_per_individual {
+                let n_loci = sample.loci.len();
+                let alleles: Vec<String> = (0..n_loci)
+                    .flat_map(|l| rows.iter().map(move |row| row[l].clone()))
+                    .collect();
+                writeln!(writer, "{} {}", leading.join(" "), alleles.join(" "))?;
+            } else {
+                for row in rows {
+                    writeln!(writer, "{} {}", leading.join(" "), row.join(" "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_structure_two_row_layout() -> Result<(), Box<dyn Error>> {
+        let data = "a b\nx 1 0.5 101 -9\nx 1 0.5 103 -9\ny 2 0.1 101 7\ny 2 0.1 101 8\n";
+        let mut sample = Sample::new();
+        sample.observe(
+            StructureBuilder::new()
+                .pop_data(true)
+                .pop_flag(true)
+                .from_reader(Box::new(data.as_bytes()))?,
+        )?;
+        assert_eq!(sample.loci_names(), vec!["a", "b"]);
+        assert_eq!(sample.variations("a").unwrap(), vec!["101", "103"]);
+        assert_eq!(sample.variations("b").unwrap(), vec!["7", "8"]);
+        assert_eq!(sample.group_names(), vec!["1", "2"]);
+        assert_eq!(sample.individuals["x"].meta["PopFlag"], "0.5");
+        Ok(())
+    }
+
+    #[test]
+    fn test_structure_one_row_round_trip() -> Result<(), Box<dyn Error>> {
+        let data = "x 1 101 103 -9 -9\ny 2 101 101 7 8\n";
+        let mut sample = Sample::new();
+        sample.observe(
+            StructureBuilder::new()
+                .marker_names(false)
+                .pop_data(true)
+                .one_row_per_individual(true)
+                .from_reader(Box::new(data.as_bytes()))?,
+        )?;
+        assert_eq!(sample.loci_names(), vec!["0", "1"]);
+
+        let mut written = vec![];
+        StructureWriter::new()
+            .marker_names(false)
+            .pop_data(true)
+            .one_row_per_individual(true)
+            .write(&sample, &mut written)?;
+        assert_eq!(String::from_utf8(written)?, data);
+
+        // Unnamed loci keep their column order beyond ten loci.
+        let data = "x 10 11 12 13 14 15 16 17 18 19 20\nx 30 31 32 33 34 35 36 37 38 39 40\n";
+        let mut sample = Sample::new();
+        sample.observe(
+            StructureBuilder::new()
+                .marker_names(false)
+                .from_reader(Box::new(data.as_bytes()))?,
+        )?;
+        assert_eq!(sample.loci_names()[2], "02");
+        assert_eq!(sample.loci_names()[10], "10");
+        let mut written = vec![];
+        StructureWriter::new()
+            .marker_names(false)
+            .write(&sample, &mut written)?;
+        assert_eq!(String::from_utf8(written)?, data);
+
+        // Allele columns must come in whole genotypes.
+        let mut sample = Sample::new();
+        let result = sample.observe(
+            StructureBuilder::new()
+                .one_row_per_individual(true)
+                .from_reader(Box::new("a b\nx 1 2 3 4 5\n".as_bytes()))?,
+        );
+        assert!(result.unwrap_err().to_string().contains("not a multiple"));
+        Ok(())
+    }
+}
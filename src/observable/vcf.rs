@@ -0,0 +1,255 @@
+use crate::prelude::*;
+use flate2::read::MultiGzDecoder;
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines, Read};
+use std::path::Path;
+
+/// Produces Observations from Variant Call Format data
+///
+/// Every record becomes a `Locus` and every sample column an individual.
+/// Alleles are named by their index in `REF,ALT`, so `0` is the reference
+/// allele. Calls with a missing allele (`.`) are observed as missing. The
+/// `CHROM`, `POS` and `REF` columns and any requested `INFO` fields
+/// annotate each `Locus`, which holds a `Snp` when `REF` and every `ALT`
+/// are single bases and is `Classical` otherwise. `Vcf` implements Iterator so it can be passed directly to
+/// `Sample::observe()`
+pub struct Vcf {
+    lines: Lines<Box<dyn BufRead>>,
+    samples: Vec<String>,
+    use_id: bool,
+    info_fields: HashSet<String>,
+    format_fields: HashSet<String>,
+    observation_buffer: VecDeque<Observation>,
+}
+
+impl Vcf {
+    /// Reads the next record into the observation buffer
+    fn read_record(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 8 {
+            return Err(format!("VCF record has {} columns, expected at least 8", columns.len()).into());
+        }
+        let locus = if self.use_id && columns[2] != "." {
+            columns[2].to_string()
+        } else {
+            format!("{}:{}", columns[0], columns[1])
+        };
+        let position = columns[1]
+            .parse::<u64>()
+            .map_err(|_| format!("VCF record `{}` has an invalid POS `{}`", locus, columns[1]))?;
+        let single_bases = columns[3]
+            .split(',')
+            .chain(columns[4].split(','))
+            .filter(|allele| *allele != ".")
+            .all(|allele| allele.len() == 1);
+        let hint = if single_bases {
+            LocusHint::Snp
+        } else {
+            LocusHint::Classical
+        };
+        let mut annotations = vec![
+            Annotation::Chromosome(columns[0].to_string()),
+            Annotation::Position(position),
+            Annotation::Reference(columns[3].to_string()),
+            Annotation::Hint(hint),
+        ];
+        for entry in columns[7].split(';') {
+            let mut parts = entry.splitn(2, '=');
+            let key = parts.next().unwrap_or_default();
+            if self.info_fields.contains(key) {
+                let value = parts.next().unwrap_or("true");
+                annotations.push(Annotation::Info(key.to_string(), value.to_string()));
+            }
+        }
+        for annotation in annotations {
+            self.observation_buffer
+                .push_back(Observation::Annotation(locus.clone(), annotation));
+        }
+
+        let format: Vec<&str> = columns.get(8).map_or(vec![], |f| f.split(':').collect());
+        let gt = format.iter().position(|key| *key == "GT");
+
+        for (sample, call) in self.samples.iter().zip(columns.iter().skip(9)) {
+            let values: Vec<&str> = call.split(':').collect();
+            if let Some(genotype) = gt.and_then(|gt| values.get(gt)) {
+                if genotype.split(['/', '|']).any(|allele| allele == ".") {
+                    self.observation_buffer
+                        .push_back(Observation::Missing(sample.clone(), locus.clone()));
+                } else {
+                    for allele in genotype.split(['/', '|']) {
+                        self.observation_buffer.push_back(Observation::Allele(
+                            sample.clone(),
+                            locus.clone(),
+                            allele.to_string(),
+                        ));
+                    }
+                }
+            }
+            for (key, value) in format.iter().zip(values.iter()) {
+                if self.format_fields.contains(*key) {
+                    self.observation_buffer.push_back(Observation::Meta(
+                        sample.clone(),
+                        format!("{}:{}", locus, key),
+                        value.to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Vcf {
+    type Item = Result<Observation, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Result<Observation, Box<dyn Error>>> {
+        while self.observation_buffer.is_empty() {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Err(e) = self.read_record(&line) {
+                return Some(Err(e));
+            }
+        }
+        self.observation_buffer.pop_front().map(Ok)
+    }
+}
+
+pub struct VcfBuilder {
+    use_id: bool,
+    info_fields: HashSet<String>,
+    format_fields: HashSet<String>,
+}
+
+impl Default for VcfBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VcfBuilder {
+    /// Construct a new Vcf builder
+    pub fn new() -> Self {
+        Self {
+            use_id: false,
+            info_fields: HashSet::new(),
+            format_fields: HashSet::new(),
+        }
+    }
+
+    /// Name loci by the `ID` column rather than `CHROM:POS`
+    ///
+    /// Records without an ID still fall back to `CHROM:POS`.
+    pub fn use_id(&mut self, use_id: bool) -> &mut Self {
+        self.use_id = use_id;
+        self
+    }
+
+    /// `INFO` fields to observe as `Annotation::Info` of each `Locus`
+    ///
+    /// Flags without a value are annotated as `true`.
+    pub fn info_fields(&mut self, info_fields: HashSet<String>) -> &mut Self {
+        self.info_fields = info_fields;
+        self
+    }
+
+    /// `FORMAT` fields to observe as `Meta` of each individual, named `locus:FIELD`
+    pub fn format_fields(&mut self, format_fields: HashSet<String>) -> &mut Self {
+        self.format_fields = format_fields;
+        self
+    }
+
+    /// Reads plain or gzip (including bgzip) compressed VCF data
+    pub fn from_reader(&self, reader: Box<dyn Read>) -> Result<Vcf, Box<dyn Error>> {
+        let mut reader = BufReader::new(reader);
+        let gzipped = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        let reader: Box<dyn BufRead> = if gzipped {
+            Box::new(BufReader::new(MultiGzDecoder::new(reader)))
+        } else {
+            Box::new(reader)
+        };
+
+        let mut lines = reader.lines();
+        let mut samples = vec![];
+        for line in lines.by_ref() {
+            let line = line?;
+            if line.starts_with("#CHROM") {
+                samples = line.split('\t').skip(9).map(|s| s.to_string()).collect();
+                break;
+            }
+            if !line.starts_with("##") {
+                return Err("VCF data has no #CHROM header line".into());
+            }
+        }
+
+        Ok(Vcf {
+            lines,
+            samples,
+            use_id: self.use_id,
+            info_fields: self.info_fields.clone(),
+            format_fields: self.format_fields.clone(),
+            observation_buffer: VecDeque::new(),
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(&self, path: P) -> Result<Vcf, Box<dyn Error>> {
+        self.from_reader(Box::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const VCF: &str = "##fileformat=VCFv4.2\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tx\ty\n\
+        1\t100\trs1\tA\tG\t.\tPASS\tDP=10;DB\tGT:DP\t0/1:4\t1|1:6\n\
+        1\t200\t.\tC\tT,G\t.\tPASS\tDP=8\tGT\t2/2\t./.\n";
+
+    #[test]
+    fn test_vcf_has_correct_loci_and_variations() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(VcfBuilder::new().from_reader(Box::new(VCF.as_bytes()))?)?;
+        assert_eq!(sample.loci_names(), vec!["1:100", "1:200"]);
+        assert_eq!(sample.variations("1:100").unwrap(), vec!["0", "1"]);
+        assert_eq!(sample.variations("1:200").unwrap(), vec!["2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gzipped_vcf_with_ids_and_meta() -> Result<(), Box<dyn Error>> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(VCF.as_bytes())?;
+        let gzipped = encoder.finish()?;
+
+        let mut sample = Sample::new();
+        sample.observe(
+            VcfBuilder::new()
+                .use_id(true)
+                .info_fields(vec!["DB".to_string()].into_iter().collect())
+                .format_fields(vec!["DP".to_string()].into_iter().collect())
+                .from_reader(Box::new(std::io::Cursor::new(gzipped)))?,
+        )?;
+        assert_eq!(sample.loci_names(), vec!["1:200", "rs1"]);
+        let y = &sample.individuals["y"];
+        assert_eq!(y.meta["rs1:DP"], "6");
+        assert!(!y.meta.contains_key("rs1:DB"));
+        let annotation = sample.locus("rs1").unwrap().annotation();
+        assert_eq!(annotation.chromosome(), Some("1"));
+        assert_eq!(annotation.position(), Some(100));
+        assert_eq!(annotation.reference(), Some("A"));
+        assert_eq!(annotation.hint(), LocusHint::Snp);
+        assert_eq!(annotation.info("DB"), Some("true"));
+        assert_eq!(annotation.info("DP"), None);
+        Ok(())
+    }
+}
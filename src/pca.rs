@@ -0,0 +1,253 @@
+use crate::linalg::symmetric_eigen;
+use crate::prelude::*;
+use ndarray::Array2;
+use std::error::Error;
+
+/// Principal component analysis of the allele frequencies of individuals
+///
+/// Each column of the `AlleleMatrix` holds the frequency of one
+/// `Variation` within each individual. Missing genotypes take the mean
+/// frequency of their column, so they sit at the origin once centred.
+pub struct Pca {
+    center: bool,
+    scale: bool,
+    components: Option<usize>,
+}
+
+impl Default for Pca {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pca {
+    /// Construct a new centred, unscaled analysis keeping every component
+    pub fn new() -> Self {
+        Self {
+            center: true,
+            scale: false,
+            components: None,
+        }
+    }
+
+    /// Whether to subtract the mean of every column
+    pub fn center(&mut self, center: bool) -> &mut Self {
+        self.center = center;
+        self
+    }
+
+    /// Whether to divide every column by its standard deviation
+    ///
+    /// Columns without variation are left unscaled.
+    pub fn scale(&mut self, scale: bool) -> &mut Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The number of components to keep scores and loadings for
+    pub fn components(&mut self, components: usize) -> &mut Self {
+        self.components = Some(components);
+        self
+    }
+}
+
+/// The principal components of an `AlleleMatrix`
+pub struct PcaSummary {
+    eigenvalues: Vec<f32>,
+    total_variance: f32,
+    scores: Array2<f32>,
+    loadings: Array2<f32>,
+    individual_names: Vec<String>,
+    alleles: Vec<(String, String)>,
+}
+
+impl PcaSummary {
+    /// The variance along every component with a positive eigenvalue
+    pub fn eigenvalues(&self) -> &[f32] {
+        &self.eigenvalues
+    }
+
+    /// The proportion of the total variance along every component
+    pub fn explained_variance(&self) -> Vec<f32> {
+        self.eigenvalues
+            .iter()
+            .map(|value| value / self.total_variance)
+            .collect()
+    }
+
+    /// The coordinates of the individuals, one row per individual and one
+    /// column per kept component
+    pub fn scores(&self) -> &Array2<f32> {
+        &self.scores
+    }
+
+    /// The unit axes of the components, one row per allele and one column
+    /// per kept component
+    pub fn loadings(&self) -> &Array2<f32> {
+        &self.loadings
+    }
+
+    /// The name of the `Individual` in each row of `scores()`
+    pub fn individual_names(&self) -> &[String] {
+        &self.individual_names
+    }
+
+    /// The `(locus, variation)` names of each row of `loadings()`
+    pub fn alleles(&self) -> &[(String, String)] {
+        &self.alleles
+    }
+}
+
+pub trait PrincipalComponentAnalysis {
+    /// Computes the principal components of the individuals' allele frequencies
+    fn pca(&mut self, pca: &Pca) -> Result<PcaSummary, Box<dyn Error>>;
+}
+
+impl Pca {
+    /// The centred and scaled frequencies of a matrix
+    fn prepare(&self, frequencies: &Array2<f32>) -> Array2<f64> {
+        let mut x = frequencies.mapv(|f| f as f64);
+        for mut column in x.gencolumns_mut() {
+            let typed: Vec<f64> = column.iter().copied().filter(|f| !f.is_nan()).collect();
+            let mean = typed.iter().sum::<f64>() / typed.len().max(1) as f64;
+            column.mapv_inplace(|f| if f.is_nan() { mean } else { f });
+            let n = column.len() as f64;
+            let sd = (column.iter().map(|f| (f - mean) * (f - mean)).sum::<f64>() / n).sqrt();
+            if self.center {
+                column.mapv_inplace(|f| f - mean);
+            }
+            if self.scale && sd > 0.0 {
+                column.mapv_inplace(|f| f / sd);
+            }
+        }
+        x
+    }
+}
+
+impl PrincipalComponentAnalysis for Sample {
+    fn pca(&mut self, pca: &Pca) -> Result<PcaSummary, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let x = pca.prepare(&self.matrix.frequency()?);
+        let n = x.nrows();
+        if n < 2 {
+            return Err("principal component analysis needs at least two individuals".into());
+        }
+
+        let (eigenvalues, scores, loadings) = principal_axes(&x);
+        let total_variance = x.map(|x| x * x).sum() / n as f64;
+        let k = pca
+            .components
+            .unwrap_or(eigenvalues.len())
+            .min(eigenvalues.len());
+
+        let keep = |a: Array2<f64>| a.slice(ndarray::s![.., ..k]).mapv(|x| x as f32);
+        Ok(PcaSummary {
+            eigenvalues: eigenvalues.iter().map(|value| *value as f32).collect(),
+            total_variance: total_variance as f32,
+            scores: keep(scores),
+            loadings: keep(loadings),
+            individual_names: self.matrix.individual_names.clone(),
+            alleles: self.allele_labels(),
+        })
+    }
+}
+
+impl Sample {
+    /// The `(locus, variation)` names of every column of the `AlleleMatrix`
+    pub(crate) fn allele_labels(&self) -> Vec<(String, String)> {
+        let mut alleles = vec![];
+        for (locus, (start, end)) in self.loci.keys().zip(self.matrix.loci.iter()) {
+            for variation in self.matrix.variation_names[*start..*end].iter() {
+                alleles.push((locus.clone(), variation.clone()));
+            }
+        }
+        alleles
+    }
+}
+
+/// The components with a positive eigenvalue of centred data `x`
+///
+/// Returns the eigenvalues, the scores of every row and the unit loadings
+/// of every column. Whichever of the covariance and Gram matrices is
+/// smaller is decomposed.
+pub(crate) fn principal_axes(x: &Array2<f64>) -> (Vec<f64>, Array2<f64>, Array2<f64>) {
+    let (n, p) = x.dim();
+    let (values, scores, loadings) = if n >= p {
+        let (values, vectors) = symmetric_eigen(&(x.t().dot(x) / n as f64));
+        let scores = x.dot(&vectors);
+        (values, scores, vectors)
+    } else {
+        let (values, vectors) = symmetric_eigen(&(x.dot(&x.t()) / n as f64));
+        let mut scores = vectors;
+        let mut loadings = x.t().dot(&scores);
+        for (k, value) in values.iter().enumerate() {
+            let norm = (n as f64 * value.max(0.0)).sqrt();
+            scores.column_mut(k).mapv_inplace(|s| s * norm);
+            if norm > 0.0 {
+                loadings.column_mut(k).mapv_inplace(|l| l / norm);
+            }
+        }
+        (values, scores, loadings)
+    };
+
+    let total_variance = x.map(|x| x * x).sum() / n as f64;
+    let tolerance = 1e-10 * total_variance.max(f64::MIN_POSITIVE);
+    let k = values
+        .iter()
+        .take_while(|value| **value > tolerance)
+        .count();
+    (
+        values[..k].to_vec(),
+        scores.slice(ndarray::s![.., ..k]).to_owned(),
+        loadings.slice(ndarray::s![.., ..k]).to_owned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+    use ndarray::Axis;
+
+    #[test]
+    fn test_pca_separates_groups() -> Result<(), Box<dyn Error>> {
+        for data in [
+            "pop,a,b\nx,1/1,1/1\nx,1/1,1/2\nx,1/2,1/1\ny,2/2,2/2\ny,2/2,2/2\ny,2/3,2/2\nz,1/1,2/2",
+            "pop,a,b\nx,1/1,1/1\nx,1/1,3/4\ny,2/2,2/2\ny,2/2,2/5",
+        ] {
+            let mut sample = Sample::new();
+            sample.observe(
+                CsvBuilder::new()
+                    .group_field("pop")
+                    .from_reader(Box::new(data.as_bytes()))?,
+            )?;
+            let summary = sample.pca(&Pca::new())?;
+            let explained = summary.explained_variance();
+            assert!((explained.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+            assert!(explained[0] > 0.5);
+            assert_eq!(summary.alleles()[0], ("a".to_string(), "1".to_string()));
+            assert_eq!(summary.loadings().nrows(), summary.alleles().len());
+
+            let pc1 = summary.scores().column(0);
+            let (x, y) = (sample.group_rows("x"), sample.group_rows("y"));
+            assert!(x.iter().all(|i| y.iter().all(|j| pc1[*i] * pc1[*j] < 0.0)));
+            let norms = summary.loadings().map_axis(Axis(0), |l| l.dot(&l));
+            assert!(norms.iter().all(|norm| (norm - 1.0).abs() < 1e-4));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scaled_columns_have_unit_variance() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,b\n1/1,1/1\n1/2,2/2\n2/2,1/2\n1/1,2/2\n1/2,1/1".as_bytes(),
+        ))?)?;
+        let summary = sample.pca(Pca::new().scale(true))?;
+        // Every one of the four allele columns contributes a variance of one.
+        assert!((summary.eigenvalues().iter().sum::<f32>() - 4.0).abs() < 1e-4);
+        Ok(())
+    }
+}
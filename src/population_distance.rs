@@ -0,0 +1,172 @@
+use crate::prelude::*;
+use crate::PairwiseMatrix;
+use std::error::Error;
+
+/// A measure of genetic dissimilarity between two populations
+///
+/// Implementations compare the allele frequencies of two populations,
+/// given as one frequency vector per locus typed in both.
+pub trait PopulationDissimilarity {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32;
+}
+
+fn sum_over<F: Fn(f32, f32) -> f32>(x: &[f32], y: &[f32], f: F) -> f32 {
+    x.iter().zip(y.iter()).map(|(x, y)| f(*x, *y)).sum()
+}
+
+/// Nei's (1972) standard genetic distance
+pub struct Nei;
+
+impl PopulationDissimilarity for Nei {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32 {
+        let (mut jxy, mut jx, mut jy) = (0.0, 0.0, 0.0);
+        for (x, y) in x.iter().zip(y.iter()) {
+            jxy += sum_over(x, y, |x, y| x * y);
+            jx += sum_over(x, x, |x, y| x * y);
+            jy += sum_over(y, y, |x, y| x * y);
+        }
+        -(jxy / (jx * jy).sqrt()).ln()
+    }
+}
+
+/// Reynolds, Weir & Cockerham's (1983) coancestry distance, `-ln(1 - theta)`
+pub struct Reynolds;
+
+impl PopulationDissimilarity for Reynolds {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32 {
+        let (mut numerator, mut denominator) = (0.0, 0.0);
+        for (x, y) in x.iter().zip(y.iter()) {
+            numerator += sum_over(x, y, |x, y| (x - y).powf(2.0));
+            denominator += 2.0 * (1.0 - sum_over(x, y, |x, y| x * y));
+        }
+        -(1.0 - numerator / denominator).ln()
+    }
+}
+
+/// Cavalli-Sforza & Edwards' (1967) chord distance, as given by Takezaki & Nei (1996)
+pub struct CavalliSforza;
+
+impl PopulationDissimilarity for CavalliSforza {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32 {
+        let chords: f32 = x
+            .iter()
+            .zip(y.iter())
+            .map(|(x, y)| (2.0 * (1.0 - sum_over(x, y, |x, y| (x * y).sqrt()))).max(0.0).sqrt())
+            .sum();
+        2.0 / (std::f32::consts::PI * x.len() as f32) * chords
+    }
+}
+
+/// Edwards' (1971) angular distance
+pub struct Edwards;
+
+impl PopulationDissimilarity for Edwards {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32 {
+        let cosines: f32 = x
+            .iter()
+            .zip(y.iter())
+            .map(|(x, y)| sum_over(x, y, |x, y| (x * y).sqrt()))
+            .sum();
+        (1.0 - cosines / x.len() as f32).max(0.0).sqrt()
+    }
+}
+
+/// Rogers' (1972) distance
+pub struct Rogers;
+
+impl PopulationDissimilarity for Rogers {
+    fn dissimilarity(&self, x: &[Vec<f32>], y: &[Vec<f32>]) -> f32 {
+        x.iter()
+            .zip(y.iter())
+            .map(|(x, y)| (sum_over(x, y, |x, y| (x - y).powf(2.0)) / 2.0).sqrt())
+            .sum::<f32>()
+            / x.len() as f32
+    }
+}
+
+pub trait PopulationDistance {
+    /// Computes the dissimilarity between the allele frequencies of every pair of `Group`s
+    fn population_distance_matrix<D: PopulationDissimilarity>(
+        &mut self,
+        measure: &D,
+    ) -> Result<PairwiseMatrix, Box<dyn Error>>;
+}
+
+impl PopulationDistance for Sample {
+    fn population_distance_matrix<D: PopulationDissimilarity>(
+        &mut self,
+        measure: &D,
+    ) -> Result<PairwiseMatrix, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let labels: Vec<String> = self.group_names().into_iter().cloned().collect();
+        let frequencies: Vec<Vec<Option<Vec<f32>>>> = labels
+            .iter()
+            .map(|group| {
+                let population = self.matrix.population_frequency(&self.group_rows(group))?;
+                Ok(self
+                    .matrix
+                    .loci
+                    .iter()
+                    .map(|(start, end)| {
+                        let locus = population.slice(ndarray::s![*start..*end]).to_vec();
+                        if locus.iter().any(|f| f.is_nan()) {
+                            None
+                        } else {
+                            Some(locus)
+                        }
+                    })
+                    .collect())
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        let mut data = ndarray::Array2::<f32>::zeros((labels.len(), labels.len()));
+        for i in 0..labels.len() {
+            for j in (i + 1)..labels.len() {
+                let (x, y): (Vec<Vec<f32>>, Vec<Vec<f32>>) = frequencies[i]
+                    .iter()
+                    .zip(frequencies[j].iter())
+                    .filter_map(|(x, y)| Some((x.clone()?, y.clone()?)))
+                    .unzip();
+                let d = measure.dissimilarity(&x, &y);
+                data[[i, j]] = d;
+                data[[j, i]] = d;
+            }
+        }
+        Ok(PairwiseMatrix { labels, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_population_distances_order_groups() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().group_field("pop").from_reader(Box::new(
+            "pop,a,b\nx,1/1,1/2\nx,1/2,1/1\ny,1/1,1/2\ny,1/2,2/2\nz,3/3,3/3\nz,3/4,3/3".as_bytes(),
+        ))?)?;
+
+        let rogers = sample.population_distance_matrix(&Rogers)?;
+        assert_eq!(rogers.labels(), ["x", "y", "z"]);
+        assert_eq!(rogers.get("x", "x"), Some(0.0));
+        assert_eq!(rogers.get("x", "y"), rogers.get("y", "x"));
+
+        let near = |m: &PairwiseMatrix| m.get("x", "y").unwrap();
+        let far = |m: &PairwiseMatrix| m.get("x", "z").unwrap();
+        assert!(near(&rogers) < far(&rogers));
+        for matrix in [
+            sample.population_distance_matrix(&Reynolds)?,
+            sample.population_distance_matrix(&CavalliSforza)?,
+            sample.population_distance_matrix(&Edwards)?,
+        ] {
+            assert!(near(&matrix) < far(&matrix));
+        }
+        assert!((far(&sample.population_distance_matrix(&Edwards)?) - 1.0).abs() < 1e-6);
+        assert!(far(&sample.population_distance_matrix(&Nei)?).is_infinite());
+        Ok(())
+    }
+}
@@ -1 +1,2 @@
-pub use crate::{Allele, Group, Individual, Locus, Observation, Sample, Variation};
+pub use crate::{Allele, Annotation, Genotype, Group, Individual, Locus, LocusHint, Observation, Sample, Variation};
+pub use crate::random::RandomSource;
@@ -0,0 +1,125 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// The source of randomness of stochastic analyses
+///
+/// Every permutation test, bootstrap and cross-validation draws from a
+/// `RandomSource`: the seed given to its builder, else the source set on
+/// the `Sample` with `Sample::set_random_source()`, else one seeded from
+/// the operating system. A seeded source gives identical results on every
+/// run. Work split across threads draws from a generator derived from the
+/// seed and the index of its piece of work, so results do not depend on
+/// the number of threads or the order they run in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RandomSource {
+    seed: Option<u64>,
+}
+
+impl RandomSource {
+    /// Construct a new source seeded from the operating system
+    pub fn new() -> Self {
+        Self { seed: None }
+    }
+
+    /// Construct a new reproducible source
+    pub fn seeded(seed: u64) -> Self {
+        Self { seed: Some(seed) }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// This source, unless an analysis was given its own seed
+    pub(crate) fn or_seed(&self, seed: Option<u64>) -> RandomSource {
+        Self {
+            seed: seed.or(self.seed),
+        }
+    }
+
+    /// A generator for work done in sequence
+    pub(crate) fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// One independent generator for each of `n` pieces of work
+    pub(crate) fn streams(&self, n: usize) -> Vec<StdRng> {
+        let base = self.seed.unwrap_or_else(|| StdRng::from_entropy().gen());
+        (0..n as u64)
+            .map(|index| StdRng::seed_from_u64(split_mix(base, index)))
+            .collect()
+    }
+}
+
+impl From<Option<u64>> for RandomSource {
+    fn from(seed: Option<u64>) -> Self {
+        Self { seed }
+    }
+}
+
+/// The `index`th output of Vigna's SplitMix64 generator started at `seed`
+fn split_mix(seed: u64, index: u64) -> u64 {
+    let mut z = seed.wrapping_add(index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_streams_are_reproducible_and_distinct() {
+        let draw = |source: RandomSource| -> Vec<u64> {
+            source.streams(4).iter_mut().map(|rng| rng.gen()).collect()
+        };
+        let streams = draw(RandomSource::seeded(3));
+        assert_eq!(streams, draw(RandomSource::seeded(3)));
+        assert_ne!(streams, draw(RandomSource::seeded(4)));
+        assert!((1..4).all(|i| !streams[..i].contains(&streams[i])));
+        assert_eq!(
+            RandomSource::seeded(3).or_seed(None),
+            RandomSource::seeded(3)
+        );
+        assert_eq!(RandomSource::new().or_seed(Some(5)).seed(), Some(5));
+    }
+
+    #[test]
+    fn test_sample_source_seeds_analyses() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::index_of_association::{IndexOfAssociation, PermutationTest};
+        use crate::observable::CsvBuilder;
+        use crate::prelude::*;
+        use crate::resampling::{Resample, Resampling, ResamplingUnit};
+
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "a,b,c\n1/1,1/2,1/1\n1/2,2/2,1/2\n2/2,1/1,2/2\n1/1,1/2,1/2\n2/2,2/2,1/1".as_bytes(),
+        ))?)?;
+        sample.set_random_source(RandomSource::seeded(11));
+
+        fn null(sample: &mut Sample, test: &PermutationTest) -> Vec<f32> {
+            let summary = sample.index_of_association_test(test).unwrap();
+            summary
+                .null_distribution()
+                .unwrap()
+                .index_of_association()
+                .to_vec()
+        }
+        let mut test = PermutationTest::new();
+        test.replicates(20);
+        assert_eq!(null(&mut sample, &test), null(&mut sample, &test));
+        assert_eq!(null(&mut sample, &test), null(&mut sample, test.seed(11)));
+
+        let mut resampling = Resampling::new(ResamplingUnit::Individuals);
+        resampling.replicates(50);
+        let statistic = |s: &mut Sample| Ok(s.index_of_association()?.rbar_d());
+        let first = sample.resample(&resampling, statistic)?;
+        let second = sample.resample(&resampling, statistic)?;
+        assert_eq!(first.replicates(), second.replicates());
+        Ok(())
+    }
+}
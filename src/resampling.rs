@@ -0,0 +1,475 @@
+use crate::distributions::{normal_quantile, quantile};
+use crate::prelude::*;
+use crate::{AlleleMatrix, Individuals, Loci};
+use rand::Rng;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+/// What is drawn to build each replicate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResamplingUnit {
+    Loci,
+    Individuals,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResamplingMethod {
+    /// Replicates draw as many units as there are, with replacement
+    Bootstrap,
+    /// Replicates leave out one unit each
+    Jackknife,
+}
+
+/// Confidence intervals of any statistic by resampling loci or individuals
+///
+/// Replicates are computed in parallel, each from its own stream of the
+/// `RandomSource`, so a seed gives the same results on any number of
+/// threads. Units drawn more than once are renamed `name#2`, `name#3` and
+/// so on. Replicates whose statistic is `NaN` are left out.
+pub struct Resampling {
+    unit: ResamplingUnit,
+    method: ResamplingMethod,
+    within_groups: bool,
+    replicates: usize,
+    confidence: f32,
+    seed: Option<u64>,
+}
+
+impl Resampling {
+    /// Construct a new bootstrap of 1000 replicates with 95% intervals
+    pub fn new(unit: ResamplingUnit) -> Self {
+        Self {
+            unit,
+            method: ResamplingMethod::Bootstrap,
+            within_groups: false,
+            replicates: 1000,
+            confidence: 0.95,
+            seed: None,
+        }
+    }
+
+    pub fn method(&mut self, method: ResamplingMethod) -> &mut Self {
+        self.method = method;
+        self
+    }
+
+    /// Whether to bootstrap individuals within the first of their `Group`s
+    /// by name, keeping the size of every group
+    ///
+    /// Individuals without a group are drawn among themselves. Only a
+    /// `Sample`'s individuals can be resampled within groups.
+    pub fn within_groups(&mut self, within_groups: bool) -> &mut Self {
+        self.within_groups = within_groups;
+        self
+    }
+
+    /// The number of bootstrap replicates, a jackknife has one per unit
+    pub fn replicates(&mut self, replicates: usize) -> &mut Self {
+        self.replicates = replicates;
+        self
+    }
+
+    pub fn confidence(&mut self, confidence: f32) -> &mut Self {
+        self.confidence = confidence;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// The variability of a statistic over resampled replicates
+pub struct ResamplingSummary {
+    estimate: f32,
+    replicates: Vec<f32>,
+    standard_error: f32,
+    interval: (f32, f32),
+}
+
+impl ResamplingSummary {
+    /// The statistic of the data as a whole
+    pub fn estimate(&self) -> f32 {
+        self.estimate
+    }
+
+    /// The statistic of every replicate
+    pub fn replicates(&self) -> &[f32] {
+        &self.replicates
+    }
+
+    pub fn standard_error(&self) -> f32 {
+        self.standard_error
+    }
+
+    /// The confidence interval of the statistic
+    ///
+    /// Bootstrap intervals are the percentiles of the replicates, jackknife
+    /// intervals are normal around the estimate.
+    pub fn interval(&self) -> (f32, f32) {
+        self.interval
+    }
+}
+
+pub trait Resample: Sized + Sync {
+    /// Computes `statistic` on this data and on every replicate
+    fn resample<F>(
+        &self,
+        resampling: &Resampling,
+        statistic: F,
+    ) -> Result<ResamplingSummary, Box<dyn Error>>
+    where
+        F: Fn(&mut Self) -> Result<f32, Box<dyn Error>> + Sync,
+    {
+        let mut summaries =
+            self.resample_many(resampling, |data: &mut Self| Ok(vec![statistic(data)?]))?;
+        Ok(summaries.remove(0))
+    }
+
+    /// Computes several `statistics` on this data and on every replicate,
+    /// summarizing each from the same replicates
+    ///
+    /// Replicates where any of the statistics is `NaN` are left out of all
+    /// of them.
+    fn resample_many<F>(
+        &self,
+        resampling: &Resampling,
+        statistics: F,
+    ) -> Result<Vec<ResamplingSummary>, Box<dyn Error>>
+    where
+        F: Fn(&mut Self) -> Result<Vec<f32>, Box<dyn Error>> + Sync;
+}
+
+/// Numbers repeated names
+#[derive(Default)]
+struct Renamer {
+    seen: HashMap<String, usize>,
+}
+
+impl Renamer {
+    fn name(&mut self, name: &str) -> String {
+        let count = self.seen.entry(name.to_owned()).or_insert(0);
+        *count += 1;
+        match *count {
+            1 => name.to_owned(),
+            n => format!("{}#{}", name, n),
+        }
+    }
+}
+
+impl Sample {
+    /// A sample of the loci and individuals at the given indices, in order
+    /// and possibly repeated
+    fn resampled(&self, loci: &[usize], individuals: &[usize]) -> Sample {
+        let originals: Vec<&Arc<Locus>> = self.loci.values().collect();
+        let mut renamer = Renamer::default();
+        let mut copies = vec![];
+        let mut resampled_loci = Loci::new();
+        let mut locus_ploidy = HashMap::new();
+        for original in loci.iter().map(|l| originals[*l]) {
+            let name = renamer.name(&original.name);
+            if let Some(ploidy) = self.locus_ploidy.get(&original.name) {
+                locus_ploidy.insert(name.clone(), *ploidy);
+            }
+            let copy = if name == original.name {
+                original.clone()
+            } else {
+                let copy = Arc::new(Locus {
+                    name: name.clone(),
+                    variations: original.variations.clone(),
+                    annotation: Mutex::new(original.annotation()),
+                });
+                copies.push((original.clone(), copy.clone()));
+                copy
+            };
+            resampled_loci.insert(name, copy);
+        }
+
+        let originals: Vec<&Individual> = self.individuals.values().collect();
+        let mut renamer = Renamer::default();
+        let mut resampled_individuals = Individuals::new();
+        for original in individuals.iter().map(|i| originals[*i]) {
+            let mut individual = original.clone();
+            individual.name = renamer.name(&original.name);
+            for (original, copy) in copies.iter() {
+                for variation in original.variations.lock().unwrap().values() {
+                    let allele = (original.clone(), variation.clone());
+                    if let Some(count) = individual.genome.get(&allele).copied() {
+                        individual
+                            .genome
+                            .insert((copy.clone(), variation.clone()), count);
+                    }
+                }
+                if let Some(alleles) = individual.alleles.get(&original.name).cloned() {
+                    individual.alleles.insert(copy.name.clone(), alleles);
+                }
+                if individual.missing.contains(&original.name) {
+                    individual.missing.insert(copy.name.clone());
+                }
+            }
+            resampled_individuals.insert(individual.name.clone(), individual);
+        }
+
+        let mut sample = self.with_data(resampled_loci, resampled_individuals);
+        sample.locus_ploidy = locus_ploidy;
+        sample
+    }
+
+    /// The indices of the individuals in each stratum of resampling
+    fn strata(&self, within_groups: bool) -> Vec<Vec<usize>> {
+        if !within_groups {
+            return vec![(0..self.individuals.len()).collect()];
+        }
+        let mut strata: HashMap<Option<&String>, Vec<usize>> = HashMap::new();
+        for (i, individual) in self.individuals.values().enumerate() {
+            let first = individual.groups.iter().map(|group| &group.name).min();
+            strata.entry(first).or_default().push(i);
+        }
+        let mut strata: Vec<(Option<&String>, Vec<usize>)> = strata.into_iter().collect();
+        strata.sort();
+        strata.into_iter().map(|(_, rows)| rows).collect()
+    }
+}
+
+impl Resampling {
+    /// The unit indices of every replicate
+    fn draws(&self, strata: &[Vec<usize>], random: RandomSource) -> Vec<Vec<usize>> {
+        match self.method {
+            ResamplingMethod::Jackknife => {
+                let mut units: Vec<usize> = strata.concat();
+                units.sort_unstable();
+                (0..units.len())
+                    .map(|left_out| {
+                        units
+                            .iter()
+                            .enumerate()
+                            .filter(|(i, _)| *i != left_out)
+                            .map(|(_, unit)| *unit)
+                            .collect()
+                    })
+                    .collect()
+            }
+            ResamplingMethod::Bootstrap => {
+                random
+                    .streams(self.replicates)
+                    .into_iter()
+                    .map(|mut rng| {
+                        let mut units = vec![];
+                        for stratum in strata.iter().filter(|stratum| !stratum.is_empty()) {
+                            for _ in 0..stratum.len() {
+                                units.push(stratum[rng.gen_range(0..stratum.len())]);
+                            }
+                        }
+                        units
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Computes the statistic of every replicate built from its draw
+    fn summarize<T, B, F>(
+        &self,
+        strata: &[Vec<usize>],
+        random: RandomSource,
+        build: B,
+        statistics: F,
+    ) -> Result<Vec<ResamplingSummary>, Box<dyn Error>>
+    where
+        B: Fn(&[usize]) -> T + Sync,
+        F: Fn(&mut T) -> Result<Vec<f32>, Box<dyn Error>> + Sync,
+    {
+        if strata.iter().map(|stratum| stratum.len()).sum::<usize>() < 2 {
+            return Err("resampling needs at least two units".into());
+        }
+        let mut units: Vec<usize> = strata.concat();
+        units.sort_unstable();
+        let estimates = statistics(&mut build(&units))?;
+        let draws = self.draws(strata, random);
+        let values: Vec<Result<Vec<f32>, String>> = draws
+            .par_iter()
+            .map(|draw| statistics(&mut build(draw)).map_err(|e| e.to_string()))
+            .collect();
+        let mut replicates = vec![];
+        for value in values {
+            let value = value?;
+            if value.len() != estimates.len() {
+                return Err("replicates must give as many statistics as the data".into());
+            }
+            replicates.push(value);
+        }
+        replicates.retain(|values| !values.iter().any(|x| x.is_nan()));
+        if replicates.is_empty() {
+            return Err("every replicate has a NaN statistic".into());
+        }
+
+        Ok(estimates
+            .iter()
+            .enumerate()
+            .map(|(s, estimate)| {
+                let replicates: Vec<f32> = replicates.iter().map(|values| values[s]).collect();
+                self.summary(*estimate, replicates)
+            })
+            .collect())
+    }
+
+    /// Summarizes the replicates of one statistic
+    fn summary(&self, estimate: f32, replicates: Vec<f32>) -> ResamplingSummary {
+        let n = replicates.len() as f64;
+        let mean = replicates.iter().map(|x| *x as f64).sum::<f64>() / n;
+        let squares: f64 = replicates.iter().map(|x| (*x as f64 - mean).powi(2)).sum();
+        let tail = (1.0 - self.confidence) / 2.0;
+        let (standard_error, interval) = match self.method {
+            ResamplingMethod::Bootstrap => {
+                let mut sorted = replicates.clone();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                (
+                    (squares / (n - 1.0).max(1.0)).sqrt() as f32,
+                    (quantile(&sorted, tail), quantile(&sorted, 1.0 - tail)),
+                )
+            }
+            ResamplingMethod::Jackknife => {
+                let standard_error = ((n - 1.0) / n * squares).sqrt() as f32;
+                let z = normal_quantile(1.0 - tail as f64) as f32;
+                (
+                    standard_error,
+                    (estimate - z * standard_error, estimate + z * standard_error),
+                )
+            }
+        };
+        ResamplingSummary {
+            estimate,
+            replicates,
+            standard_error,
+            interval,
+        }
+    }
+}
+
+impl Resample for Sample {
+    fn resample_many<F>(
+        &self,
+        resampling: &Resampling,
+        statistics: F,
+    ) -> Result<Vec<ResamplingSummary>, Box<dyn Error>>
+    where
+        F: Fn(&mut Self) -> Result<Vec<f32>, Box<dyn Error>> + Sync,
+    {
+        let loci: Vec<usize> = (0..self.loci.len()).collect();
+        let individuals: Vec<usize> = (0..self.individuals.len()).collect();
+        let random = self.random.or_seed(resampling.seed);
+        match resampling.unit {
+            ResamplingUnit::Loci => {
+                if resampling.within_groups {
+                    return Err("only individuals can be resampled within groups".into());
+                }
+                let build = |draw: &[usize]| self.resampled(draw, &individuals);
+                resampling.summarize(&[loci], random, build, statistics)
+            }
+            ResamplingUnit::Individuals => {
+                let build = |draw: &[usize]| self.resampled(&loci, draw);
+                let strata = self.strata(resampling.within_groups);
+                resampling.summarize(&strata, random, build, statistics)
+            }
+        }
+    }
+}
+
+impl Resample for AlleleMatrix {
+    fn resample_many<F>(
+        &self,
+        resampling: &Resampling,
+        statistics: F,
+    ) -> Result<Vec<ResamplingSummary>, Box<dyn Error>>
+    where
+        F: Fn(&mut Self) -> Result<Vec<f32>, Box<dyn Error>> + Sync,
+    {
+        if resampling.within_groups {
+            return Err("resampling within groups needs a Sample".into());
+        }
+        let random: RandomSource = resampling.seed.into();
+        match resampling.unit {
+            ResamplingUnit::Loci => {
+                let loci: Vec<usize> = (0..self.loci.len()).collect();
+                resampling.summarize(&[loci], random, |draw| self.select_loci(draw), statistics)
+            }
+            ResamplingUnit::Individuals => {
+                let rows: Vec<usize> = (0..self.individuals()).collect();
+                resampling.summarize(&[rows], random, |draw| self.select_rows(draw), statistics)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::f_statistics::FStatistics;
+    use crate::observable::CsvBuilder;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().group_field("pop").from_reader(Box::new(
+            "pop,a,b,c\nx,1/1,1/2,1/1\nx,1/1,2/2,1/2\nx,1/2,1/1,1/1\ny,2/2,1/2,2/2\ny,2/2,2/2,1/2\ny,2/3,1/1,2/2"
+                .as_bytes(),
+        ))?)?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_jackknife_of_a_mean_matches_its_standard_error() -> Result<(), Box<dyn Error>> {
+        let mut sample = sample()?;
+        sample.flush()?;
+        let mean = |matrix: &mut AlleleMatrix| {
+            let column = matrix.data().column(0);
+            Ok(column.iter().map(|x| *x as f32).sum::<f32>() / column.len() as f32)
+        };
+        let summary = sample.matrix.resample(
+            Resampling::new(ResamplingUnit::Individuals).method(ResamplingMethod::Jackknife),
+            mean,
+        )?;
+        // Copies of allele 1 at locus a are 2, 2, 1, 0, 0, 0.
+        let (n, mean, sd) = (6.0f32, 5.0 / 6.0, (29.0f32 / 30.0).sqrt());
+        assert_eq!(summary.replicates().len(), 6);
+        assert!((summary.estimate() - mean).abs() < 1e-6);
+        assert!((summary.standard_error() - sd / n.sqrt()).abs() < 1e-5);
+        let (lower, upper) = summary.interval();
+        assert!((mean - lower - 1.959_964 * sd / n.sqrt()).abs() < 1e-4);
+        assert!((upper - mean - 1.959_964 * sd / n.sqrt()).abs() < 1e-4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_is_seeded_and_keeps_units() -> Result<(), Box<dyn Error>> {
+        let sample = sample()?;
+        let loci = sample.resample(
+            Resampling::new(ResamplingUnit::Loci).replicates(50).seed(1),
+            |s| Ok(s.loci_names().len() as f32),
+        )?;
+        assert!(loci.replicates().iter().all(|n| *n == 3.0));
+
+        let mut within = Resampling::new(ResamplingUnit::Individuals);
+        within.within_groups(true).replicates(200).seed(7);
+        let theta = |s: &mut Sample| Ok(s.f_statistics(None)?.overall().theta());
+        let first = sample.resample(&within, theta)?;
+        let second = sample.resample(&within, theta)?;
+        assert_eq!(first.replicates(), second.replicates());
+        assert_eq!(first.replicates().len(), 200);
+        let (lower, upper) = first.interval();
+        assert!(lower <= first.estimate() && first.estimate() <= upper + 1e-6);
+        assert!(first.standard_error() > 0.0);
+
+        // Several statistics are summarized from the same replicates.
+        let both = sample.resample_many(&within, |s: &mut Sample| {
+            let theta = s.f_statistics(None)?.overall().theta();
+            Ok(vec![theta, -theta])
+        })?;
+        assert_eq!(both[0].replicates(), first.replicates());
+        let negated: Vec<f32> = both[1].replicates().iter().map(|x| -x).collect();
+        assert_eq!(both[0].replicates(), &negated[..]);
+        Ok(())
+    }
+}
@@ -0,0 +1,450 @@
+use crate::prelude::*;
+use crate::PairwiseMatrix;
+use rand::seq::SliceRandom;
+use std::error::Error;
+use std::ops::Add;
+
+/// Slatkin's (1995) Rst at one `Locus`, or over all loci
+///
+/// Rst is estimated by an analysis of variance of the allele sizes of the
+/// gene copies in every `Group` (Michalakis & Excoffier 1996), with sizes
+/// in repeat units so that loci weigh alike. `fst` is the same estimator
+/// with every pair of distinct alleles one unit apart, so that the two
+/// only differ in whether allele sizes carry information.
+pub struct LocusRst {
+    locus: Option<String>,
+    rst: f32,
+    fst: f32,
+    permuted_rst: Option<f32>,
+    p_value: Option<f32>,
+}
+
+impl LocusRst {
+    /// The name of the `Locus`, or `None` over all loci
+    pub fn locus(&self) -> Option<&str> {
+        self.locus.as_deref()
+    }
+
+    pub fn rst(&self) -> f32 {
+        self.rst
+    }
+
+    pub fn fst(&self) -> f32 {
+        self.fst
+    }
+
+    /// The mean Rst over permutations of allele sizes, if they were run
+    pub fn permuted_rst(&self) -> Option<f32> {
+        self.permuted_rst
+    }
+
+    /// The proportion of permutations of allele sizes with an Rst at least
+    /// as large, if they were run
+    ///
+    /// A small p-value means stepwise mutation contributed to the
+    /// differentiation, and Rst is to be preferred over Fst.
+    pub fn p_value(&self) -> Option<f32> {
+        self.p_value
+    }
+}
+
+/// Rst between all `Group`s
+pub struct RstSummary {
+    loci: Vec<LocusRst>,
+    overall: LocusRst,
+}
+
+impl RstSummary {
+    /// The statistics of every microsatellite locus
+    pub fn loci(&self) -> &[LocusRst] {
+        &self.loci
+    }
+
+    /// The multilocus statistics
+    pub fn overall(&self) -> &LocusRst {
+        &self.overall
+    }
+}
+
+/// Tests Rst against Fst by permuting allele sizes (Hardy et al. 2003)
+///
+/// Each permutation randomly reassigns the sizes of the alleles of every
+/// locus among its alleles, which keeps the allele frequencies but
+/// breaks any relation between sizes and differentiation.
+pub struct AlleleSizePermutation {
+    permutations: usize,
+    seed: Option<u64>,
+}
+
+impl Default for AlleleSizePermutation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AlleleSizePermutation {
+    /// Construct a new test with 999 permutations
+    pub fn new() -> Self {
+        Self {
+            permutations: 999,
+            seed: None,
+        }
+    }
+
+    pub fn permutations(&mut self, permutations: usize) -> &mut Self {
+        self.permutations = permutations;
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+pub trait StepwiseDifferentiation {
+    /// Computes Rst between all `Group`s at every microsatellite locus
+    fn rst(
+        &mut self,
+        permutation: Option<&AlleleSizePermutation>,
+    ) -> Result<RstSummary, Box<dyn Error>>;
+
+    /// Computes Rst between every pair of `Group`s
+    fn pairwise_rst(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>>;
+
+    /// Computes Goldstein et al.'s (1995) `(δμ)²` between every pair of
+    /// `Group`s, the squared difference of their mean allele sizes in
+    /// repeat units averaged over microsatellite loci
+    fn delta_mu_squared(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>>;
+}
+
+/// The variance components among and within groups
+///
+/// Summing components over loci gives the multilocus estimators.
+#[derive(Clone, Copy, Default)]
+struct Components {
+    among: f64,
+    within: f64,
+}
+
+impl Add for Components {
+    type Output = Components;
+
+    fn add(self, other: Components) -> Components {
+        Components {
+            among: self.among + other.among,
+            within: self.within + other.within,
+        }
+    }
+}
+
+impl Components {
+    fn ratio(&self) -> f32 {
+        (self.among / (self.among + self.within)) as f32
+    }
+}
+
+/// The gene copies of one microsatellite locus
+struct SizedLocus {
+    name: String,
+    /// The number of copies of every allele in every group
+    counts: Vec<Vec<f64>>,
+    /// The size of every allele in repeat units
+    sizes: Vec<f64>,
+}
+
+/// The sum of squared deviations of a set of gene copies
+///
+/// Without sizes, distinct alleles are one unit apart.
+fn sum_of_squares(counts: &[f64], sizes: Option<&[f64]>) -> f64 {
+    let n: f64 = counts.iter().sum();
+    if n == 0.0 {
+        return 0.0;
+    }
+    match sizes {
+        Some(sizes) => {
+            let mean = counts.iter().zip(sizes).map(|(c, x)| c * x).sum::<f64>() / n;
+            counts
+                .iter()
+                .zip(sizes)
+                .map(|(c, x)| c * (x - mean) * (x - mean))
+                .sum()
+        }
+        None => (n - counts.iter().map(|c| c * c).sum::<f64>() / n) / 2.0,
+    }
+}
+
+impl SizedLocus {
+    fn components(&self, sizes: Option<&[f64]>) -> Components {
+        let sizes_in_group: Vec<f64> = self.counts.iter().map(|c| c.iter().sum()).collect();
+        let n: f64 = sizes_in_group.iter().sum();
+        let k = sizes_in_group.iter().filter(|size| **size > 0.0).count() as f64;
+        if k < 2.0 || n <= k {
+            return Components::default();
+        }
+        let total: Vec<f64> = (0..self.sizes.len())
+            .map(|allele| self.counts.iter().map(|c| c[allele]).sum())
+            .collect();
+        let within: f64 = self.counts.iter().map(|c| sum_of_squares(c, sizes)).sum();
+        let among = sum_of_squares(&total, sizes) - within;
+
+        let n0 = (n - sizes_in_group.iter().map(|size| size * size).sum::<f64>() / n) / (k - 1.0);
+        let ms_among = among / (k - 1.0);
+        let ms_within = within / (n - k);
+        Components {
+            among: (ms_among - ms_within) / n0,
+            within: ms_within,
+        }
+    }
+
+    fn rst(&self) -> Components {
+        self.components(Some(&self.sizes))
+    }
+
+    fn fst(&self) -> Components {
+        self.components(None)
+    }
+
+    /// The mean size of the copies in one group, if it has any
+    fn mean_size(&self, group: usize) -> Option<f64> {
+        let n: f64 = self.counts[group].iter().sum();
+        if n == 0.0 {
+            return None;
+        }
+        Some(
+            self.counts[group]
+                .iter()
+                .zip(&self.sizes)
+                .map(|(c, x)| c * x)
+                .sum::<f64>()
+                / n,
+        )
+    }
+}
+
+impl Sample {
+    /// The gene copies of every microsatellite locus in the named groups
+    ///
+    /// Loci are microsatellites when every allele has a size.
+    fn sized_loci(&mut self, groups: &[&String]) -> Result<Vec<SizedLocus>, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let rows: Vec<Vec<usize>> = groups.iter().map(|group| self.group_rows(group)).collect();
+        let mut loci = vec![];
+        for (l, name) in self.loci.keys().enumerate() {
+            let (start, end) = self.matrix.loci[l];
+            let sizes: Option<Vec<f64>> = (start..end)
+                .map(|column| self.matrix.allele_sizes.get(column).copied().flatten())
+                .map(|size| size.map(|size| size as f64))
+                .collect();
+            let sizes = match sizes {
+                Some(sizes) if !sizes.is_empty() => sizes,
+                _ => continue,
+            };
+            let repeat = self
+                .matrix
+                .repeat_lengths
+                .get(l)
+                .copied()
+                .flatten()
+                .unwrap_or(1) as f64;
+            loci.push(SizedLocus {
+                name: name.clone(),
+                counts: rows
+                    .iter()
+                    .map(|rows| {
+                        (start..end)
+                            .map(|column| {
+                                rows.iter()
+                                    .map(|row| self.matrix.data[[*row, column]] as f64)
+                                    .sum()
+                            })
+                            .collect()
+                    })
+                    .collect(),
+                sizes: sizes.iter().map(|size| size / repeat).collect(),
+            });
+        }
+        if loci.is_empty() {
+            return Err("no microsatellite locus has numeric allele sizes".into());
+        }
+        Ok(loci)
+    }
+
+    /// The names of all groups, which must number at least two
+    fn rst_groups(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let groups: Vec<String> = self.group_names().into_iter().cloned().collect();
+        if groups.len() < 2 {
+            return Err("Rst needs at least two groups".into());
+        }
+        Ok(groups)
+    }
+}
+
+impl StepwiseDifferentiation for Sample {
+    fn rst(
+        &mut self,
+        permutation: Option<&AlleleSizePermutation>,
+    ) -> Result<RstSummary, Box<dyn Error>> {
+        let groups = self.rst_groups()?;
+        let mut loci = self.sized_loci(&groups.iter().collect::<Vec<_>>())?;
+        let rst: Vec<Components> = loci.iter().map(|locus| locus.rst()).collect();
+        let fst: Vec<Components> = loci.iter().map(|locus| locus.fst()).collect();
+        let sum = |components: &[Components]| {
+            components
+                .iter()
+                .fold(Components::default(), |sum, c| sum + *c)
+        };
+        let observed: Vec<f32> = rst.iter().map(|c| c.ratio()).collect();
+        let overall = sum(&rst).ratio();
+
+        // (sum of permuted Rst, permutations at least as large) of every
+        // locus, then of all loci
+        let mut tallies = vec![(0.0, 0); loci.len() + 1];
+        if let Some(permutation) = permutation {
+            let mut rng = self.random.or_seed(permutation.seed).rng();
+            for _ in 0..permutation.permutations {
+                let mut permuted = vec![];
+                for (l, locus) in loci.iter_mut().enumerate() {
+                    locus.sizes.shuffle(&mut rng);
+                    let components = locus.rst();
+                    tally(&mut tallies[l], components.ratio(), observed[l]);
+                    permuted.push(components);
+                }
+                tally(&mut tallies[loci.len()], sum(&permuted).ratio(), overall);
+            }
+        }
+        let test = |(total, extreme): (f64, usize)| match permutation {
+            Some(permutation) if permutation.permutations > 0 => (
+                Some((total / permutation.permutations as f64) as f32),
+                Some((extreme + 1) as f32 / (permutation.permutations + 1) as f32),
+            ),
+            _ => (None, None),
+        };
+
+        let (permuted_rst, p_value) = test(tallies[loci.len()]);
+        Ok(RstSummary {
+            loci: loci
+                .iter()
+                .enumerate()
+                .map(|(l, locus)| {
+                    let (permuted_rst, p_value) = test(tallies[l]);
+                    LocusRst {
+                        locus: Some(locus.name.clone()),
+                        rst: observed[l],
+                        fst: fst[l].ratio(),
+                        permuted_rst,
+                        p_value,
+                    }
+                })
+                .collect(),
+            overall: LocusRst {
+                locus: None,
+                rst: overall,
+                fst: sum(&fst).ratio(),
+                permuted_rst,
+                p_value,
+            },
+        })
+    }
+
+    fn pairwise_rst(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>> {
+        let labels = self.rst_groups()?;
+        let mut data = ndarray::Array2::<f32>::zeros((labels.len(), labels.len()));
+        for i in 0..labels.len() {
+            for j in (i + 1)..labels.len() {
+                let rst = self
+                    .sized_loci(&[&labels[i], &labels[j]])?
+                    .iter()
+                    .fold(Components::default(), |sum, locus| sum + locus.rst())
+                    .ratio();
+                data[[i, j]] = rst;
+                data[[j, i]] = rst;
+            }
+        }
+        Ok(PairwiseMatrix { labels, data })
+    }
+
+    fn delta_mu_squared(&mut self) -> Result<PairwiseMatrix, Box<dyn Error>> {
+        let labels = self.rst_groups()?;
+        let loci = self.sized_loci(&labels.iter().collect::<Vec<_>>())?;
+        let mut data = ndarray::Array2::<f32>::zeros((labels.len(), labels.len()));
+        for i in 0..labels.len() {
+            for j in (i + 1)..labels.len() {
+                let squares: Vec<f64> = loci
+                    .iter()
+                    .filter_map(|locus| {
+                        let difference = locus.mean_size(i)? - locus.mean_size(j)?;
+                        Some(difference * difference)
+                    })
+                    .collect();
+                let delta = (squares.iter().sum::<f64>() / squares.len() as f64) as f32;
+                data[[i, j]] = delta;
+                data[[j, i]] = delta;
+            }
+        }
+        Ok(PairwiseMatrix { labels, data })
+    }
+}
+
+/// Adds a permuted statistic to a tally
+fn tally((total, extreme): &mut (f64, usize), permuted: f32, observed: f32) {
+    if permuted.is_nan() {
+        return;
+    }
+    *total += permuted as f64;
+    if permuted >= observed {
+        *extreme += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    fn sample() -> Result<Sample, Box<dyn Error>> {
+        // Group y carries alleles far larger than x at locus a, but only
+        // slightly larger at locus b. Locus c holds SNPs.
+        let mut sample = Sample::new();
+        sample.observe(
+            CsvBuilder::new()
+                .group_field("pop")
+                .locus_hints(
+                    vec![("c".to_string(), LocusHint::Snp)]
+                        .into_iter()
+                        .collect(),
+                )
+                .from_reader(Box::new(
+                    "pop,a,b,c\nx,10/10,10/11,A/A\nx,10/11,10/10,A/G\nx,11/11,11/11,A/A\n\
+                     y,30/30,11/12,G/G\ny,30/31,11/12,G/G\ny,31/31,11/12,A/G"
+                        .as_bytes(),
+                ))?,
+        )?;
+        Ok(sample)
+    }
+
+    #[test]
+    fn test_rst_reflects_allele_sizes() -> Result<(), Box<dyn Error>> {
+        let mut sample = sample()?;
+        let summary = sample.rst(Some(AlleleSizePermutation::new().permutations(99).seed(1)))?;
+        let loci: Vec<Option<&str>> = summary.loci().iter().map(|l| l.locus()).collect();
+        assert_eq!(loci, vec![Some("a"), Some("b")]);
+
+        let a = &summary.loci()[0];
+        assert!(a.rst() > 0.9);
+        assert!(a.rst() > a.fst());
+        assert!(a.p_value().unwrap() <= 1.0);
+        assert!(a.permuted_rst().unwrap() < a.rst());
+        let overall = summary.overall();
+        assert!(overall.rst() > overall.fst());
+
+        let pairwise = sample.pairwise_rst()?;
+        assert!((pairwise.get("x", "y").unwrap() - overall.rst()).abs() < 1e-6);
+        let delta = sample.delta_mu_squared()?;
+        // Means differ by 20 repeats at a and by 1 repeat at b.
+        assert!((delta.get("x", "y").unwrap() - (400.0 + 1.0) / 2.0).abs() < 1e-3);
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use crate::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+/// The extent of each window along a chromosome
+pub enum Window {
+    /// Windows spanning a number of base pairs
+    Size(u64),
+    /// Windows holding a number of consecutive loci
+    Count(usize),
+}
+
+/// The index of association in windows sliding along the genome
+///
+/// Loci are placed by the coordinates given to `coordinates()`, or else
+/// by their annotated chromosome and position, or else by their names
+/// when these have the form `chromosome:position`. Loci without a position are left out.
+/// Windows advance by `step`, the size of a window by default, restart
+/// at the first locus of every chromosome and are only reported when
+/// they hold at least two loci whose distances between individuals vary.
+/// Consecutive windows holding the same loci are reported once.
+pub struct SlidingWindow {
+    window: Window,
+    step: Option<u64>,
+    coordinates: HashMap<String, (String, u64)>,
+}
+
+impl SlidingWindow {
+    /// Construct a new analysis of non-overlapping windows
+    pub fn new(window: Window) -> Self {
+        Self {
+            window,
+            step: None,
+            coordinates: HashMap::new(),
+        }
+    }
+
+    /// The distance between the starts of consecutive windows, in base
+    /// pairs for `Window::Size` and in loci for `Window::Count`
+    pub fn step(&mut self, step: u64) -> &mut Self {
+        self.step = Some(step);
+        self
+    }
+
+    /// Places the named locus at a position of a chromosome
+    pub fn coordinates(&mut self, locus: &str, chromosome: &str, position: u64) -> &mut Self {
+        self.coordinates
+            .insert(locus.to_owned(), (chromosome.to_owned(), position));
+        self
+    }
+}
+
+/// The index of association of the loci in one window
+pub struct WindowRecord {
+    chromosome: String,
+    start: u64,
+    end: u64,
+    loci: Vec<String>,
+    index_of_association: f32,
+    rbar_d: f32,
+}
+
+impl WindowRecord {
+    pub fn chromosome(&self) -> &str {
+        &self.chromosome
+    }
+
+    /// The position of the first locus in the window
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// The position of the last locus in the window
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The names of the loci in the window, in order of position
+    pub fn loci(&self) -> &[String] {
+        &self.loci
+    }
+
+    pub fn index_of_association(&self) -> f32 {
+        self.index_of_association
+    }
+
+    pub fn rbar_d(&self) -> f32 {
+        self.rbar_d
+    }
+}
+
+/// The index of association of every window along the genome
+pub struct WindowTable {
+    windows: Vec<WindowRecord>,
+}
+
+impl WindowTable {
+    pub fn windows(&self) -> &[WindowRecord] {
+        &self.windows
+    }
+
+    /// Writes one comma separated row per window, preceded by a header
+    pub fn write_csv<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record([
+            "chromosome",
+            "start",
+            "end",
+            "loci",
+            "index_of_association",
+            "rbar_d",
+        ])?;
+        for window in self.windows.iter() {
+            wtr.write_record(&[
+                window.chromosome.clone(),
+                window.start.to_string(),
+                window.end.to_string(),
+                window.loci.len().to_string(),
+                window.index_of_association.to_string(),
+                window.rbar_d.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+pub trait WindowedAssociation {
+    /// Computes the index of association in every window
+    fn sliding_index_of_association(
+        &mut self,
+        window: &SlidingWindow,
+    ) -> Result<WindowTable, Box<dyn Error>>;
+}
+
+/// Parses a `chromosome:position` locus name
+fn parse_coordinates(name: &str) -> Option<(String, u64)> {
+    let (chromosome, position) = name.rsplit_once(':')?;
+    Some((chromosome.to_owned(), position.parse().ok()?))
+}
+
+/// Orders chromosomes numerically when both names are numbers
+fn compare_chromosomes(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u64>(), b.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        _ => a.cmp(b),
+    }
+}
+
+impl WindowedAssociation for Sample {
+    fn sliding_index_of_association(
+        &mut self,
+        window: &SlidingWindow,
+    ) -> Result<WindowTable, Box<dyn Error>> {
+        if self.matrix.dirty {
+            self.flush()?;
+        }
+        let names: Vec<&String> = self.loci.keys().collect();
+        // (chromosome, position, index of the locus in the matrix)
+        let mut placed: Vec<(String, u64, usize)> = self
+            .loci
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (name, locus))| {
+                let annotation = locus.annotation();
+                let annotated = match (annotation.chromosome(), annotation.position()) {
+                    (Some(chromosome), Some(position)) => Some((chromosome.to_owned(), position)),
+                    _ => None,
+                };
+                let (chromosome, position) = window
+                    .coordinates
+                    .get(name)
+                    .cloned()
+                    .or(annotated)
+                    .or_else(|| parse_coordinates(name))?;
+                Some((chromosome, position, index))
+            })
+            .collect();
+        if placed.is_empty() {
+            return Err("no locus has a chromosome and position".into());
+        }
+        placed.sort_by(|a, b| compare_chromosomes(&a.0, &b.0).then(a.1.cmp(&b.1)));
+
+        let mut windows = vec![];
+        let mut first = 0;
+        while first < placed.len() {
+            let chromosome = &placed[first].0;
+            let last = first
+                + placed[first..]
+                    .iter()
+                    .take_while(|l| l.0 == *chromosome)
+                    .count();
+            let loci = &placed[first..last];
+            let spans: Vec<&[(String, u64, usize)]> = match window.window {
+                Window::Count(count) => {
+                    let step = window.step.map_or(count, |step| step as usize).max(1);
+                    (0..loci.len())
+                        .step_by(step)
+                        .map(|start| &loci[start..(start + count).min(loci.len())])
+                        .collect()
+                }
+                Window::Size(size) => {
+                    let step = window.step.unwrap_or(size).max(1);
+                    let end = loci[loci.len() - 1].1;
+                    let mut spans = vec![];
+                    let (mut from, mut to) = (0, 0);
+                    let mut start = loci[0].1;
+                    while start <= end {
+                        while loci[from].1 < start {
+                            from += 1;
+                        }
+                        to = to.max(from);
+                        while to < loci.len() && loci[to].1 < start + size {
+                            to += 1;
+                        }
+                        // Windows holding the same loci as the last are reported once.
+                        if spans.last() != Some(&(from, to)) {
+                            spans.push((from, to));
+                        }
+                        start += step;
+                    }
+                    spans.into_iter().map(|(from, to)| &loci[from..to]).collect()
+                }
+            };
+            for span in spans.into_iter().filter(|span| span.len() >= 2) {
+                let indices: Vec<usize> = span.iter().map(|l| l.2).collect();
+                let summary = match self.matrix.select_loci(&indices).association()? {
+                    Some((summary, _)) => summary,
+                    None => continue,
+                };
+                windows.push(WindowRecord {
+                    chromosome: chromosome.clone(),
+                    start: span[0].1,
+                    end: span[span.len() - 1].1,
+                    loci: indices.iter().map(|i| names[*i].clone()).collect(),
+                    index_of_association: summary.index_of_association(),
+                    rbar_d: summary.rbar_d(),
+                });
+            }
+            first = last;
+        }
+        Ok(WindowTable { windows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observable::CsvBuilder;
+
+    #[test]
+    fn test_windows_follow_chromosomes() -> Result<(), Box<dyn Error>> {
+        let mut sample = Sample::new();
+        sample.observe(CsvBuilder::new().from_reader(Box::new(
+            "1:100,1:200,1:300,2:50,2:150,x\n1/1,1/1,1/2,1/1,1/2,1/1\n1/2,1/2,2/2,2/2,1/1,1/2\n2/2,2/2,1/1,1/2,2/2,2/2"
+                .as_bytes(),
+        ))?)?;
+
+        let by_count =
+            sample.sliding_index_of_association(&SlidingWindow::new(Window::Count(2)))?;
+        let by_size =
+            sample.sliding_index_of_association(&SlidingWindow::new(Window::Size(150)))?;
+        for table in [&by_count, &by_size] {
+            let windows = table.windows();
+            assert_eq!(windows.len(), 2);
+            assert_eq!(windows[0].loci(), ["1:100", "1:200"]);
+            assert_eq!(
+                (
+                    windows[1].chromosome(),
+                    windows[1].start(),
+                    windows[1].end()
+                ),
+                ("2", 50, 150)
+            );
+        }
+        // Identical loci are fully associated.
+        assert!((by_count.windows()[0].rbar_d() - 1.0).abs() < 1e-6);
+
+        let mut sliding = SlidingWindow::new(Window::Count(2));
+        sliding.step(1).coordinates("x", "2", 100);
+        let windows = sample.sliding_index_of_association(&sliding)?;
+        assert_eq!(windows.windows().len(), 4);
+        assert_eq!(windows.windows()[3].loci(), ["x", "2:150"]);
+
+        // Overlapping windows holding the same loci are reported once.
+        let mut sliding = SlidingWindow::new(Window::Size(250));
+        sliding.step(50);
+        let windows = sample.sliding_index_of_association(&sliding)?;
+        let loci: Vec<String> = windows.windows().iter().map(|w| w.loci().join(" ")).collect();
+        assert_eq!(loci, ["1:100 1:200 1:300", "1:200 1:300", "2:50 2:150"]);
+
+        // Annotated coordinates place a locus too.
+        for annotation in [
+            Annotation::Chromosome("2".to_string()),
+            Annotation::Position(100),
+        ] {
+            sample._observe(Observation::Annotation("x".to_string(), annotation));
+        }
+        let mut sliding = SlidingWindow::new(Window::Count(2));
+        sliding.step(1);
+        let windows = sample.sliding_index_of_association(&sliding)?;
+        assert_eq!(windows.windows()[3].loci(), ["x", "2:150"]);
+        Ok(())
+    }
+}